@@ -0,0 +1,49 @@
+//! Record build metadata for 'quickenv version --verbose'.
+
+use std::path::Path;
+use std::process::Command;
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+}
+
+fn main() {
+    let git_sha = command_output("git", &["rev-parse", "--short", "HEAD"]);
+    println!(
+        "cargo:rustc-env=QUICKENV_GIT_SHA={}",
+        git_sha.as_deref().unwrap_or("unknown")
+    );
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let rustc_version = command_output(&rustc, &["--version"]);
+    println!(
+        "cargo:rustc-env=QUICKENV_RUSTC_VERSION={}",
+        rustc_version.as_deref().unwrap_or("unknown")
+    );
+
+    println!(
+        "cargo:rustc-env=QUICKENV_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+
+    // Only watch files that exist, cargo reruns the script every time otherwise. Packaged crates
+    // have no .git at all.
+    let head = Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Some(current_ref) = std::fs::read_to_string(head)
+            .ok()
+            .and_then(|x| Some(x.strip_prefix("ref: ")?.trim().to_owned()))
+        {
+            let ref_path = Path::new(".git").join(current_ref);
+            if ref_path.exists() {
+                println!("cargo:rerun-if-changed={}", ref_path.display());
+            }
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,37 @@
+//! Shims that run a program of a different name, created with 'quickenv shim --target'.
+//!
+//! Aliases are stored in the quickenv home and therefore apply to every .envrc, just like the
+//! shims themselves.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::core::Error;
+
+fn get_alias_path(quickenv_home: &Path, name: &str) -> PathBuf {
+    quickenv_home.join("aliases").join(name)
+}
+
+/// Look up the command the shim `name` should run instead of its own name, if any.
+pub fn get_alias(quickenv_home: &Path, name: &str) -> Result<Option<String>, Error> {
+    match std::fs::read_to_string(get_alias_path(quickenv_home, name)) {
+        Ok(target) => Ok(Some(target.trim().to_owned())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn set_alias(quickenv_home: &Path, name: &str, target: &str) -> Result<(), Error> {
+    std::fs::create_dir_all(quickenv_home.join("aliases"))?;
+    std::fs::write(get_alias_path(quickenv_home, name), target)?;
+    Ok(())
+}
+
+/// Returns whether there was an alias to remove.
+pub fn remove_alias(quickenv_home: &Path, name: &str) -> Result<bool, Error> {
+    match std::fs::remove_file(get_alias_path(quickenv_home, name)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
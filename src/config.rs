@@ -0,0 +1,39 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::core::{find_envrc, Error};
+
+/// Name of the per-project config file, expected next to .envrc.
+pub const PROJECT_CONFIG_FILENAME: &str = ".quickenv.toml";
+
+#[derive(Deserialize, Default, Debug)]
+pub struct ProjectConfig {
+    /// Default log filter for this project, in the same syntax as QUICKENV_LOG.
+    pub log_level: Option<String>,
+    /// Set to false to disable warnings about unshimmed commands for this project.
+    /// QUICKENV_NO_SHIM_WARNINGS takes precedence if set.
+    pub shim_warnings: Option<bool>,
+    /// Commands that 'quickenv shim' should never recommend for this project.
+    pub shim_exclude: Option<Vec<String>>,
+}
+
+pub fn load_project_config(root: &Path) -> Result<ProjectConfig, Error> {
+    let path = root.join(PROJECT_CONFIG_FILENAME);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ProjectConfig::default()),
+        Err(e) => return Err(e.into()),
+    };
+
+    toml::from_str(&contents).map_err(|e| Error::InvalidConfig(path, e))
+}
+
+/// Look up log_level from the config of the project in the current directory.
+///
+/// This runs before the logger is initialized, so any failure to find or parse the config is
+/// ignored here. It will be reported once the context is resolved for real.
+pub fn project_log_level() -> Option<String> {
+    let (root, _, _) = find_envrc().ok()?;
+    load_project_config(&root).ok()?.log_level
+}
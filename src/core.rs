@@ -1,16 +1,25 @@
 use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
-use std::io::{self, BufRead, BufReader};
+use std::io;
 use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::{Path, PathBuf};
 
+use crate::config::{load_project_config, ProjectConfig};
+
 pub type Env = BTreeMap<OsString, OsString>;
 
+/// Environment variables in the order they are stored in the env cache.
+pub type EnvList = Vec<(OsString, OsString)>;
+
 pub struct EnvrcContext {
     pub envrc: std::fs::File,
+    pub envrc_path: PathBuf,
     pub root: PathBuf,
     pub env_cache_path: PathBuf,
     pub env_cache_dir: PathBuf,
+    /// Identifies the .envrc by its path. Used as filename for its cache and trust entries.
+    pub key: String,
+    pub config: ProjectConfig,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -23,47 +32,265 @@ pub enum Error {
     NoQuickenvHome,
     #[error("failed to get current directory")]
     CurrentDir(#[source] io::Error),
+    #[error("failed to parse {}", .0.display())]
+    InvalidConfig(PathBuf, #[source] toml::de::Error),
+    #[error("failed to find a git repository in current or any parent directory")]
+    NoGitRoot,
+    #[error("{} is not a valid quickenv cache file", .0.display())]
+    InvalidCache(PathBuf),
+    #[error("{} is not trusted. Review its contents, then run 'quickenv trust' to allow executing it", .0.display())]
+    Untrusted(PathBuf),
+    #[error(".envrc exited with status {0}")]
+    EnvrcFailed(std::process::ExitStatus),
+    #[error("failed to find bash, which is required to run .envrc")]
+    NoShell,
+    #[error("invalid entry {0:?} in QUICKENV_SEARCH_BOUNDARY, expected 'git' or an absolute path")]
+    InvalidSearchBoundary(String),
 }
 
-pub fn resolve_envrc_context(quickenv_home: &Path) -> Result<EnvrcContext, Error> {
+/// Where the search for .envrc stops, as configured with QUICKENV_SEARCH_BOUNDARY.
+#[derive(Default)]
+struct SearchBoundary {
+    /// The last directory to look in, the root of the enclosing git repository.
+    git_root: Option<PathBuf>,
+    /// Directories that are not looked in, nor anything above them, unless the search starts
+    /// there.
+    ceilings: Vec<PathBuf>,
+}
+
+impl SearchBoundary {
+    /// Parse QUICKENV_SEARCH_BOUNDARY, a colon-separated list of 'git' and absolute paths.
+    fn from_env() -> Result<Self, Error> {
+        let mut rv = SearchBoundary::default();
+        let Some(value) = std::env::var_os("QUICKENV_SEARCH_BOUNDARY") else {
+            return Ok(rv);
+        };
+
+        for entry in std::env::split_paths(&value) {
+            if entry.as_os_str().is_empty() {
+                continue;
+            } else if entry.as_os_str() == "git" {
+                // outside of a git repository, there is no boundary to apply
+                rv.git_root = find_git_root().ok();
+            } else if entry.is_absolute() {
+                rv.ceilings
+                    .push(std::fs::canonicalize(&entry).unwrap_or(entry));
+            } else {
+                return Err(Error::InvalidSearchBoundary(
+                    entry.to_string_lossy().into_owned(),
+                ));
+            }
+        }
+
+        Ok(rv)
+    }
+}
+
+/// Walk up from the current directory and open the nearest .envrc.
+///
+/// Returns the directory containing the .envrc, its path, and the opened file.
+pub fn find_envrc() -> Result<(PathBuf, PathBuf, std::fs::File), Error> {
+    let boundary = SearchBoundary::from_env()?;
     let mut root = std::env::current_dir().map_err(Error::CurrentDir)?;
+    let start = root.clone();
+
+    loop {
+        if root != start && boundary.ceilings.contains(&root) {
+            log::debug!(
+                "not looking for .envrc in {}, it is in QUICKENV_SEARCH_BOUNDARY",
+                root.display()
+            );
+            return Err(Error::NoEnvrc);
+        }
 
-    let (envrc_path, envrc) = loop {
         let path = root.join(".envrc");
         if let Ok(f) = std::fs::File::open(&path) {
-            log::debug!("loading {}", path.display());
-            break (path, f);
+            return Ok((root, path, f));
+        }
+
+        if boundary.git_root.as_ref() == Some(&root) {
+            log::debug!(
+                "not looking for .envrc above the git repository at {}",
+                root.display()
+            );
+            return Err(Error::NoEnvrc);
         }
 
         if !root.pop() {
             return Err(Error::NoEnvrc);
         }
+    }
+}
+
+/// Walk up from the current directory to the root of the enclosing git repository.
+pub fn find_git_root() -> Result<PathBuf, Error> {
+    let mut root = std::env::current_dir().map_err(Error::CurrentDir)?;
+
+    loop {
+        // .git is a file rather than a directory in linked worktrees and submodules
+        if root.join(".git").exists() {
+            return Ok(root);
+        }
+
+        if !root.pop() {
+            return Err(Error::NoGitRoot);
+        }
+    }
+}
+
+pub fn resolve_envrc_context(quickenv_home: &Path) -> Result<EnvrcContext, Error> {
+    let (root, envrc_path, envrc) = find_envrc()?;
+    // The same project may be reachable through symlinked directories, which must all share one
+    // cache and trust entry. Only the directory is canonicalized: a symlinked .envrc file still
+    // belongs to the project it is in.
+    let (root, envrc_path, legacy_envrc_path) = match std::fs::canonicalize(&root) {
+        Ok(root) => {
+            let canonical_envrc_path = root.join(".envrc");
+            (root, canonical_envrc_path, Some(envrc_path))
+        }
+        Err(_) => (root, envrc_path, None),
     };
+    log::debug!("loading {}", envrc_path.display());
+    // A broken config must not make every shim in the project unusable.
+    let config = load_project_config(&root).unwrap_or_else(|e| {
+        match std::error::Error::source(&e) {
+            Some(source) => log::warn!("{e}, using the default config: {source}"),
+            None => log::warn!("{e}, using the default config"),
+        }
+        ProjectConfig::default()
+    });
 
-    let env_cache_dir = quickenv_home.join("envs/");
+    let env_cache_dir = get_env_cache_dir(quickenv_home);
 
-    let mut env_hasher = blake3::Hasher::new();
-    env_hasher.update(envrc_path.as_os_str().as_bytes());
-    let env_cache_path = env_cache_dir.join(hex::encode(env_hasher.finalize().as_bytes()));
+    let key = get_envrc_key(&envrc_path);
+    let env_cache_path = env_cache_dir.join(&key);
+
+    if let Some(legacy_envrc_path) = legacy_envrc_path.filter(|path| *path != envrc_path) {
+        adopt_legacy_key(
+            quickenv_home,
+            &env_cache_dir,
+            &get_envrc_key(&legacy_envrc_path),
+            &key,
+        );
+    }
 
     Ok(EnvrcContext {
         root,
         env_cache_dir,
         envrc,
+        envrc_path,
         env_cache_path,
+        key,
+        config,
     })
 }
 
+fn get_envrc_key(envrc_path: &Path) -> String {
+    let mut env_hasher = blake3::Hasher::new();
+    env_hasher.update(envrc_path.as_os_str().as_bytes());
+    hex::encode(env_hasher.finalize().as_bytes())
+}
+
+/// Older versions of quickenv keyed caches and trust entries by the .envrc path as found, without
+/// resolving symlinks. Move such entries over to the canonical key, so that projects opened
+/// through a symlink don't have to be trusted and reloaded again. Entries that already exist under
+/// the canonical key win. Failures are not fatal, the project just looks untrusted or uncached.
+fn adopt_legacy_key(quickenv_home: &Path, env_cache_dir: &Path, legacy_key: &str, key: &str) {
+    let legacy_cache_path = env_cache_dir.join(legacy_key);
+    let cache_path = env_cache_dir.join(key);
+    for (from, to) in [
+        (
+            crate::trust::get_trust_path_for_key(quickenv_home, legacy_key),
+            crate::trust::get_trust_path_for_key(quickenv_home, key),
+        ),
+        (
+            legacy_cache_path.with_extension("failed"),
+            cache_path.with_extension("failed"),
+        ),
+        (
+            legacy_cache_path.with_extension("reloaded"),
+            cache_path.with_extension("reloaded"),
+        ),
+        (legacy_cache_path, cache_path),
+    ] {
+        if !from.exists() || to.exists() {
+            continue;
+        }
+        match std::fs::rename(&from, &to) {
+            Ok(()) => log::debug!("moved {} to {}", from.display(), to.display()),
+            Err(e) => log::debug!("failed to move {} to {}: {e}", from.display(), to.display()),
+        }
+    }
+}
+
+/// Directory for quickenv's persistent state, such as shims and trusted .envrc files.
+///
+/// This is QUICKENV_HOME if set, else ~/.quickenv if it already exists, else
+/// $XDG_DATA_HOME/quickenv.
 pub fn get_quickenv_home() -> Result<PathBuf, Error> {
     if let Ok(home) = std::env::var("QUICKENV_HOME") {
-        Ok(Path::new(&home).to_owned())
-    } else if let Ok(home) = std::env::var("HOME") {
-        Ok(Path::new(&home).join(".quickenv/"))
-    } else {
-        Err(Error::NoQuickenvHome)
+        return Ok(Path::new(&home).to_owned());
+    }
+
+    let home = std::env::var_os("HOME").ok_or(Error::NoQuickenvHome)?;
+    let legacy_home = get_legacy_quickenv_home(&home);
+    if legacy_home.exists() {
+        return Ok(legacy_home);
+    }
+
+    Ok(get_xdg_quickenv_home(&home))
+}
+
+/// The single directory quickenv used to store everything in, before following the XDG spec.
+pub fn get_legacy_quickenv_home(home: &OsStr) -> PathBuf {
+    Path::new(home).join(".quickenv/")
+}
+
+pub fn get_xdg_quickenv_home(home: &OsStr) -> PathBuf {
+    get_xdg_dir("XDG_DATA_HOME", home, ".local/share").join("quickenv/")
+}
+
+pub fn get_xdg_env_cache_dir(home: &OsStr) -> PathBuf {
+    get_xdg_dir("XDG_CACHE_HOME", home, ".cache").join("quickenv/envs/")
+}
+
+/// Directory where the environment variables of each .envrc are cached.
+///
+/// In the XDG layout this lives in $XDG_CACHE_HOME/quickenv, otherwise in the quickenv home.
+pub fn get_env_cache_dir(quickenv_home: &Path) -> PathBuf {
+    if let (None, Some(home)) = (std::env::var_os("QUICKENV_HOME"), std::env::var_os("HOME")) {
+        if quickenv_home == get_xdg_quickenv_home(&home) {
+            return get_xdg_env_cache_dir(&home);
+        }
+    }
+
+    quickenv_home.join("envs/")
+}
+
+/// Read an XDG base directory variable, falling back to a directory within HOME if it is unset or
+/// not an absolute path, as mandated by the spec.
+fn get_xdg_dir(var_name: &str, home: &OsStr, fallback: &str) -> PathBuf {
+    match std::env::var_os(var_name) {
+        Some(dir) if Path::new(&dir).is_absolute() => PathBuf::from(dir),
+        _ => Path::new(home).join(fallback),
+    }
+}
+
+/// Directory where shims are created. Defaults to `bin/` in the quickenv home, and can be
+/// relocated using QUICKENV_BIN_DIR.
+pub fn get_bin_dir(quickenv_home: &Path) -> PathBuf {
+    match std::env::var_os("QUICKENV_BIN_DIR") {
+        Some(bin_dir) if !bin_dir.is_empty() => PathBuf::from(bin_dir),
+        _ => quickenv_home.join("bin/"),
     }
 }
 
+/// Parse one line of the output of `env` into `env`. Lines without '=' continue the value of the
+/// previous variable, as values may contain newlines.
+///
+/// If a variable occurs more than once, the last value wins, like it does for `getenv` in most
+/// programs. That is logged, as it is usually caused by an .envrc exporting a value that contains a
+/// newline followed by what looks like another variable.
 pub fn parse_env_line(line: &[u8], env: &mut Env, prev_var_name: &mut Option<OsString>) {
     let mut split_iter = line.splitn(2, |&x| x == b'=');
 
@@ -75,7 +302,12 @@ pub fn parse_env_line(line: &[u8], env: &mut Env, prev_var_name: &mut Option<OsS
             let var_name = OsString::from_vec(var_name.to_owned());
             let value = OsString::from_vec(value.to_owned());
             *prev_var_name = Some(var_name.clone());
-            env.insert(var_name, value);
+            if env.insert(var_name, value).is_some() {
+                log::debug!(
+                    "{:?} is set more than once, using its last value",
+                    prev_var_name.as_ref().unwrap()
+                );
+            }
         }
         None => {
             let prev_value = env.get_mut(prev_var_name.as_ref().unwrap()).unwrap();
@@ -86,24 +318,283 @@ pub fn parse_env_line(line: &[u8], env: &mut Env, prev_var_name: &mut Option<OsS
 }
 
 pub fn get_envvars(ctx: &EnvrcContext) -> Result<Option<Env>, Error> {
-    if let Ok(file) = std::fs::File::open(&ctx.env_cache_path) {
-        let mut loaded_env_cache = BTreeMap::new();
-        let reader = BufReader::new(file);
+    Ok(get_envvars_ordered(ctx)?.map(|envvars| envvars.into_iter().collect()))
+}
+
+/// Like `get_envvars`, but keeps the variables in the order of the cache file.
+pub fn get_envvars_ordered(ctx: &EnvrcContext) -> Result<Option<EnvList>, Error> {
+    match std::fs::read(&ctx.env_cache_path) {
+        Ok(contents) => parse_env_cache(&contents)
+            .map(Some)
+            .ok_or_else(|| Error::InvalidCache(ctx.env_cache_path.clone())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Read an env cache from an arbitrary path, such as the cache of another project.
+pub fn read_env_cache(path: &Path) -> Result<EnvList, Error> {
+    let contents = std::fs::read(path)?;
+    parse_env_cache(&contents).ok_or_else(|| Error::InvalidCache(path.to_owned()))
+}
+
+/// Whether the env cache exists and was written after the .envrc was last changed.
+pub fn is_cache_fresh(ctx: &EnvrcContext) -> bool {
+    let envrc_mtime = ctx.envrc.metadata().and_then(|x| x.modified()).ok();
+    let cache_mtime = std::fs::metadata(&ctx.env_cache_path)
+        .and_then(|x| x.modified())
+        .ok();
+    matches!((envrc_mtime, cache_mtime), (Some(a), Some(b)) if b >= a)
+}
+
+/// File next to the env cache that records why the last reload failed, if it did.
+pub fn get_reload_failure_path(ctx: &EnvrcContext) -> PathBuf {
+    ctx.env_cache_path.with_extension("failed")
+}
+
+/// File next to the env cache whose mtime records when the .envrc was last reloaded successfully.
+/// The cache itself keeps its mtime if the variables did not change.
+pub fn get_last_reload_path(ctx: &EnvrcContext) -> PathBuf {
+    ctx.env_cache_path.with_extension("reloaded")
+}
+
+pub fn get_reload_failure(ctx: &EnvrcContext) -> Option<String> {
+    std::fs::read_to_string(get_reload_failure_path(ctx)).ok()
+}
+
+/// Start of the header that env caches begin with, followed by the path of their .envrc.
+const CACHE_HEADER_PREFIX: &[u8] = b"#quickenv-cache envrc=";
+
+/// Serialize environment variables for the env cache.
+///
+/// Each variable is written as `KEY=VALUE` followed by a NUL byte. Neither keys nor values can
+/// contain NUL, so unlike newlines this delimiter is unambiguous.
+///
+/// If `envrc_path` is given, it is recorded in a header record before the variables.
+pub fn serialize_env_cache<'a>(
+    envrc_path: Option<&Path>,
+    env: impl IntoIterator<Item = (&'a OsStr, &'a OsStr)>,
+) -> Vec<u8> {
+    let mut rv = Vec::new();
+    if let Some(envrc_path) = envrc_path {
+        rv.extend_from_slice(CACHE_HEADER_PREFIX);
+        rv.extend_from_slice(envrc_path.as_os_str().as_bytes());
+        rv.push(b'\0');
+    }
+    for (key, value) in env {
+        rv.extend_from_slice(key.as_bytes());
+        rv.push(b'=');
+        rv.extend_from_slice(value.as_bytes());
+        rv.push(b'\0');
+    }
+    rv
+}
+
+/// Serialize variables into the format of caches written before the header and NUL delimiters
+/// were introduced: `KEY=VALUE` lines in alphabetical order.
+///
+/// A value containing a newline followed by `=` can't be told apart from another variable in this
+/// format, so prefer `serialize_env_cache` unless a tool requires the old format.
+pub fn serialize_legacy_env_cache<'a>(
+    env: impl IntoIterator<Item = (&'a OsStr, &'a OsStr)>,
+) -> Vec<u8> {
+    let mut rv = Vec::new();
+    for (key, value) in env.into_iter().collect::<BTreeMap<_, _>>() {
+        rv.extend_from_slice(key.as_bytes());
+        rv.push(b'=');
+        rv.extend_from_slice(value.as_bytes());
+        rv.push(b'\n');
+    }
+    rv
+}
+
+/// Split off the header of an env cache, returning the path of the .envrc it records and the
+/// remaining contents. Caches without a header are returned unchanged.
+fn split_env_cache_header(contents: &[u8]) -> (Option<PathBuf>, &[u8]) {
+    let Some(rest) = contents.strip_prefix(CACHE_HEADER_PREFIX) else {
+        return (None, contents);
+    };
+
+    match rest.iter().position(|&x| x == b'\0') {
+        Some(end) => (
+            Some(PathBuf::from(OsStr::from_bytes(&rest[..end]))),
+            &rest[end + 1..],
+        ),
+        None => (None, contents),
+    }
+}
 
-        let mut prev_var_name = None;
+/// Read the path of the .envrc an env cache belongs to from its header, if it has one.
+pub fn parse_env_cache_header(contents: &[u8]) -> Option<PathBuf> {
+    split_env_cache_header(contents).0
+}
+
+/// Parse the contents of an env cache, as written by `serialize_env_cache`, with or without a
+/// header.
+///
+/// Caches written by older versions of quickenv delimit variables by newlines instead, and are
+/// still understood. They never contain NUL bytes.
+///
+/// Variables are returned in the order they were written in.
+pub fn parse_env_cache(contents: &[u8]) -> Option<EnvList> {
+    let (_, contents) = split_env_cache_header(contents);
+    if !contents.contains(&b'\0') {
+        // legacy caches were always written in alphabetical order
+        let mut env = BTreeMap::new();
+        let contents = contents.strip_suffix(b"\n").unwrap_or(contents);
+        if !contents.is_empty() {
+            // every line without '=' continues the previous value, so the first needs one
+            if !contents.split(|&x| x == b'\n').next()?.contains(&b'=') {
+                return None;
+            }
 
-        for line in reader.split(b'\n') {
-            let raw_line = line?;
-            let mut line = raw_line.as_slice();
-            while let Some(b'\n') = line.last() {
-                line = &line[..line.len()];
+            let mut prev_var_name = None;
+            for line in contents.split(|&x| x == b'\n') {
+                parse_env_line(line, &mut env, &mut prev_var_name);
             }
+        }
+
+        return Some(env.into_iter().collect());
+    }
+
+    let mut env = Vec::new();
+    for record in contents.split(|&x| x == b'\0') {
+        if record.is_empty() {
+            continue;
+        }
+
+        let mut split_iter = record.splitn(2, |&x| x == b'=');
+        let key = split_iter.next()?;
+        let value = split_iter.next()?;
+        env.push((
+            OsString::from_vec(key.to_owned()),
+            OsString::from_vec(value.to_owned()),
+        ));
+    }
+
+    Some(env)
+}
+
+#[test]
+fn test_env_cache_roundtrip() {
+    // xorshift, to generate the same values every time without pulling in a dependency
+    let mut state = 0x2545f4914f6cdd1du64;
+    let mut next = move |max: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % max
+    };
+
+    for _ in 0..1000 {
+        let mut env = Env::new();
 
-            parse_env_line(line, &mut loaded_env_cache, &mut prev_var_name);
+        for _ in 0..next(5) {
+            let key: Vec<u8> = (0..=next(8))
+                .map(|_| b"ABCXYZ_019"[next(10) as usize])
+                .collect();
+            // newlines and equal signs are the interesting bytes, so make them likely
+            let value: Vec<u8> = (0..next(20))
+                .map(|_| match next(4) {
+                    0 => b'\n',
+                    1 => b'=',
+                    _ => next(255) as u8 + 1,
+                })
+                .collect();
+            env.insert(OsString::from_vec(key), OsString::from_vec(value));
         }
 
-        return Ok(Some(loaded_env_cache));
+        for envrc_path in [None, Some(Path::new("/project/.envrc"))] {
+            let serialized = serialize_env_cache(
+                envrc_path,
+                env.iter().map(|(k, v)| (k.as_os_str(), v.as_os_str())),
+            );
+            assert_eq!(
+                parse_env_cache(&serialized),
+                Some(env.clone().into_iter().collect())
+            );
+            assert_eq!(parse_env_cache_header(&serialized).as_deref(), envrc_path);
+        }
     }
+}
+
+#[test]
+fn test_env_cache_order() {
+    let serialized = serialize_env_cache(
+        None,
+        [
+            (OsStr::new("ZZZ"), OsStr::new("1")),
+            (OsStr::new("AAA"), OsStr::new("2")),
+        ],
+    );
+    assert_eq!(
+        parse_env_cache(&serialized),
+        Some(vec![("ZZZ".into(), "1".into()), ("AAA".into(), "2".into())])
+    );
+}
+
+#[test]
+fn test_adopt_legacy_key() {
+    let home = tempfile::tempdir().unwrap();
+    let home = home.path();
+    let env_cache_dir = get_env_cache_dir(home);
+    std::fs::create_dir_all(&env_cache_dir).unwrap();
+    std::fs::create_dir_all(home.join("trusted")).unwrap();
+
+    std::fs::write(env_cache_dir.join("old"), "legacy cache").unwrap();
+    std::fs::write(env_cache_dir.join("old.failed"), "legacy failure").unwrap();
+    std::fs::write(home.join("trusted/old"), "legacy trust").unwrap();
+    std::fs::write(home.join("trusted/new"), "current trust").unwrap();
+
+    adopt_legacy_key(home, &env_cache_dir, "old", "new");
+
+    let read = |path: PathBuf| std::fs::read_to_string(path).ok();
+    assert_eq!(
+        read(env_cache_dir.join("new")).as_deref(),
+        Some("legacy cache")
+    );
+    assert_eq!(
+        read(env_cache_dir.join("new.failed")).as_deref(),
+        Some("legacy failure")
+    );
+    assert_eq!(read(env_cache_dir.join("old")), None);
+    // the trust entry under the canonical key is newer, so it is kept
+    assert_eq!(
+        read(home.join("trusted/new")).as_deref(),
+        Some("current trust")
+    );
+    assert_eq!(
+        read(home.join("trusted/old")).as_deref(),
+        Some("legacy trust")
+    );
+}
+
+#[test]
+fn test_legacy_env_cache_roundtrip() {
+    let serialized = serialize_legacy_env_cache([
+        (OsStr::new("ZZZ"), OsStr::new("1")),
+        (OsStr::new("AAA"), OsStr::new("line1\nline2")),
+    ]);
+    assert_eq!(serialized, b"AAA=line1\nline2\nZZZ=1\n");
+    assert_eq!(
+        parse_env_cache(&serialized),
+        Some(vec![
+            ("AAA".into(), "line1\nline2".into()),
+            ("ZZZ".into(), "1".into()),
+        ])
+    );
+}
 
-    Ok(None)
+#[test]
+fn test_parse_legacy_env_cache() {
+    assert_eq!(
+        parse_env_cache(b"FOO=bar\nMULTI=line1\nline2\n"),
+        Some(vec![
+            ("FOO".into(), "bar".into()),
+            ("MULTI".into(), "line1\nline2".into()),
+        ])
+    );
+    assert_eq!(parse_env_cache(b""), Some(EnvList::new()));
+    assert_eq!(parse_env_cache(b"garbage\n"), None);
+    assert_eq!(parse_env_cache(b"FOO=bar\0garbage\0"), None);
 }
@@ -0,0 +1,77 @@
+//! Directories in which shims never load any .envrc, managed with 'quickenv deny'.
+//!
+//! The list is stored in a single file in the quickenv home's config directory, one path or glob
+//! pattern per line.
+//! A pattern denies the directories it matches and everything below them.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::core::Error;
+
+fn get_deny_list_path(quickenv_home: &Path) -> PathBuf {
+    quickenv_home.join("config").join("deny")
+}
+
+pub fn read_deny_list(quickenv_home: &Path) -> Result<Vec<String>, Error> {
+    match std::fs::read_to_string(get_deny_list_path(quickenv_home)) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_owned)
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn write_deny_list(quickenv_home: &Path, patterns: &[String]) -> Result<(), Error> {
+    std::fs::create_dir_all(quickenv_home.join("config"))?;
+    let mut contents = String::new();
+    for pattern in patterns {
+        contents.push_str(pattern);
+        contents.push('\n');
+    }
+    std::fs::write(get_deny_list_path(quickenv_home), contents)?;
+    Ok(())
+}
+
+/// Resolve symlinks in the part of a pattern before its first glob, as the project directories it
+/// is matched against are canonical. Directories that don't exist yet are kept as they are.
+pub fn canonicalize_pattern(pattern: &Path) -> PathBuf {
+    let components: Vec<_> = pattern.components().collect();
+    let literal_len = components
+        .iter()
+        .position(|component| {
+            component
+                .as_os_str()
+                .as_encoded_bytes()
+                .iter()
+                .any(|x| b"*?[".contains(x))
+        })
+        .unwrap_or(components.len());
+
+    for len in (1..=literal_len).rev() {
+        let prefix: PathBuf = components[..len].iter().collect();
+        if let Ok(mut rv) = std::fs::canonicalize(prefix) {
+            rv.extend(&components[len..]);
+            return rv;
+        }
+    }
+
+    pattern.to_owned()
+}
+
+/// Returns the pattern that denies loading the .envrc in `root`, if any.
+pub fn find_denying_pattern(quickenv_home: &Path, root: &Path) -> Result<Option<String>, Error> {
+    for pattern in read_deny_list(quickenv_home)? {
+        let matches_glob = glob::Pattern::new(&pattern)
+            .is_ok_and(|glob| root.ancestors().any(|dir| glob.matches_path(dir)));
+        if matches_glob || root.starts_with(&pattern) {
+            return Ok(Some(pattern));
+        }
+    }
+
+    Ok(None)
+}
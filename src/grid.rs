@@ -1,27 +1,53 @@
+use std::io::{self, Write};
+
+use console::Term;
 use term_grid::{Direction, Filling, Grid, GridOptions};
 
 pub fn print_as_grid<T: AsRef<str>>(strings: &[T]) {
-    if print_as_grid_inner(strings).is_none() {
+    if print_as_grid_inner(&Term::stderr(), strings).is_none() {
         for string in strings {
             eprintln!("{}", string.as_ref());
         }
     }
 }
 
-fn print_as_grid_inner<T: AsRef<str>>(strings: &[T]) -> Option<()> {
-    let width = console::Term::stderr()
-        .size_checked()
-        .map(|(_rows, cols)| cols)?;
+/// Like print_as_grid, but for an arbitrary terminal.
+pub fn print_as_grid_to<T: AsRef<str>>(term: &Term, strings: &[T]) -> io::Result<()> {
+    if print_as_grid_inner(term, strings).is_none() {
+        for string in strings {
+            term.write_line(string.as_ref())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The width of the terminal, or COLUMNS if it can't be determined, such as when the output is
+/// not a terminal or inside some terminal emulators.
+fn get_width(term: &Term) -> Option<usize> {
+    if let Some((_rows, cols)) = term.size_checked() {
+        return Some(cols.into());
+    }
+
+    std::env::var("COLUMNS")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&x| x > 0)
+}
+
+fn print_as_grid_inner<T: AsRef<str>>(term: &Term, strings: &[T]) -> Option<()> {
+    let width = get_width(term)?;
 
     let grid = Grid::new(
         strings.iter().collect(),
         GridOptions {
             filling: Filling::Spaces(2),
             direction: Direction::LeftToRight,
-            width: width.into(),
+            width,
         },
     );
 
-    eprint!("{grid}");
-    Some(())
+    write!(&*term, "{grid}").ok()
 }
@@ -1,23 +1,31 @@
 use std::collections::{BTreeMap, BTreeSet};
 
 use std::ffi::{OsStr, OsString};
-use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::io::{self, BufRead, BufReader, Read, Write};
 
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::symlink;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process::{self, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use log::{Level, LevelFilter};
 
 use anyhow::{Context, Error};
+use base64::Engine;
 use clap::Parser;
 use console::style;
 
+mod alias;
+mod config;
 mod core;
+mod deny;
 mod grid;
+mod missing;
 mod signals;
+mod trust;
 
 use crate::core::resolve_envrc_context;
 
@@ -29,28 +37,230 @@ use crate::core::resolve_envrc_context;
     disable_colored_help = true,
     after_help = "ENVIRONMENT VARIABLES:
     QUICKENV_LOG=debug to enable debug output (in shim commands as well)
-    QUICKENV_LOG=error to silence everything but errors
-    QUICKENV_NO_SHIM=1 to disable loading of .envrc, and effectively disable shims
+    QUICKENV_LOG=quickenv::shim=debug to enable debug output for one part of quickenv only: quickenv::shim (running shims), quickenv::reload (running the .envrc), quickenv::core (finding the .envrc and cache), quickenv::signals (Ctrl-C handling)
+    QUICKENV_LOG=error to silence everything but errors. Takes precedence over log_level in .quickenv.toml
+    QUICKENV_NO_SHIM=1 to disable loading of .envrc, and effectively disable shims. Shims set it to the path of the .envrc they loaded, so that nested shims skip loading that same .envrc again
     QUICKENV_SHIM_EXEC=1 to directly exec() shims instead of spawning them as subprocess. This can help with attaching debuggers.
-    QUICKENV_NO_SHIM_WARNINGS=1 to disable nags about running 'quickenv shim' everytime a new binary is added
+    QUICKENV_SHIM_OPTS='exec debug' to change how a single shim invocation behaves. A space- or comma-separated list of: 'exec' (same as QUICKENV_SHIM_EXEC=1), 'no-env' (same as QUICKENV_NO_SHIM=1), 'path-only' (same as QUICKENV_PATH_ONLY=1), 'debug' (same as QUICKENV_LOG=debug)
+    QUICKENV_PATH_ONLY=1 to make shims only apply PATH from .envrc, and leave all other variables as they are. This avoids surprises from stale cached variables, but breaks tools that rely on anything else the .envrc sets up, such as VIRTUAL_ENV
+    QUICKENV_SHELL=/bin/zsh to change the shell used by 'quickenv exec --shell'. Defaults to SHELL
+    QUICKENV_SEARCH_BOUNDARY=git:$HOME to limit how far up quickenv looks for the .envrc. A colon-separated list of 'git' (stop at the root of the enclosing git repository) and directories that are never looked in, nor anything above them, unless they are the current directory. By default, the search goes all the way up to /
+    QUICKENV_HOME=/path/to/dir to store quickenv's state in a different directory than ~/.local/share/quickenv/ (or ~/.quickenv/ if it exists). Cached environment variables are then stored there as well, instead of in ~/.cache/quickenv/.
+    QUICKENV_BIN_DIR=/path/to/dir to create shims in a different directory than ~/.local/share/quickenv/bin/. That directory needs to be on PATH instead.
+    QUICKENV_SHIM_EXCLUDE='sudo rm' to never recommend these commands when running 'quickenv shim' without arguments. Defaults to 'sudo su doas rm'. Shimming them explicitly still works
+    QUICKENV_NO_SHIM_WARNINGS=1 to disable nags about running 'quickenv shim' everytime a new binary is added. Set 'shim_warnings = false' in .quickenv.toml to do this for one project only
     QUICKENV_PRELUDE='eval \"$(direnv stdlib)\"' can be overridden to something else to get rid of the direnv stdlib and therefore direnv dependency, or to inject additional code before executing each envrc.
+    QUICKENV_NATIVE_BEFORE_ENV=1 to make 'quickenv reload' compute the environment before running the .envrc itself, instead of asking bash for it. This is faster for very large environments.
+    QUICKENV_BASH_ARGS='-e -o pipefail' to pass additional options to bash when running the .envrc during 'quickenv reload', for example to make it fail on errors
+    QUICKENV_LOCALE=C to run the .envrc during 'quickenv reload' with LC_ALL set to that locale, such that the output of the commands it runs does not depend on the user's locale. LC_ALL itself is not cached. Defaults to inheriting the locale
+    QUICKENV_RELOAD_MARKER=1 to make shims print 'quickenv:reload-required <path to .envrc>' to stderr when the .envrc needs to be reloaded, because its cache is missing or outdated, or the last reload failed. Meant for editor integrations
+    QUICKENV_PTY=1 to run the .envrc during 'quickenv reload' with a pseudo-terminal as stdout instead of a pipe, for programs that behave differently when not writing to a terminal.
+    QUICKENV_IDLE_TIMEOUT=30 to kill the .envrc during 'quickenv reload' if it prints nothing to stdout for that many seconds.
+    QUICKENV_MISSING_CACHE_TTL=5 to let shims remember for that many seconds that their program could not be found, such that running them repeatedly fails fast. A program installed in the meantime is reported missing until then. Disabled by default
+    COLUMNS=80 to lay out lists of commands in columns of that total width, when the size of the terminal cannot be determined.
+
+EXIT CODES:
+    1 for errors not listed below
+    3 if there is no .envrc in the current or any parent directory
+    4 if 'quickenv vars' or 'quickenv exec --from-cache' finds no cached variables, because 'quickenv reload' was never run
+    5 if the .envrc failed during 'quickenv reload'
+    6 if bash could not be found to run the .envrc
+    7 if the .envrc has not been trusted yet
+    8 if a file could not be accessed due to missing permissions
 "
 )]
 struct Args {
+    /// Whether to use colors in output. 'auto' uses colors when writing to a terminal, unless
+    /// NO_COLOR is set.
+    #[clap(long, global = true, value_enum, default_value = "auto")]
+    color: ColorChoice,
     #[clap(subcommand)]
     subcommand: Command,
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum VarsSort {
+    None,
+    Name,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ShellKind {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn apply(self) {
+        let enabled = match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                if std::env::var_os("NO_COLOR").is_some_and(|x| !x.is_empty()) {
+                    false
+                } else {
+                    // keep console's own terminal detection
+                    return;
+                }
+            }
+        };
+
+        console::set_colors_enabled(enabled);
+        console::set_colors_enabled_stderr(enabled);
+    }
+}
+
 #[derive(Parser, Debug)]
 enum Command {
     /// Execute .envrc in the current or parent directory, and cache the new variables.
-    Reload,
+    Reload {
+        /// Discard the output of .envrc instead of forwarding it to stdout. Errors are still
+        /// reported.
+        #[clap(long, short)]
+        quiet: bool,
+        /// Use the .envrc at the root of the enclosing git repository, instead of the nearest one.
+        #[clap(long)]
+        git_root: bool,
+        /// Print the resulting environment variables in the same format as 'quickenv vars',
+        /// instead of writing them to the cache. The output of .envrc goes to stderr instead.
+        #[clap(long)]
+        print: bool,
+        /// Keep running, and reload again whenever the .envrc (or .envrc.local) changes or is
+        /// trusted again. Stop with Ctrl-C.
+        #[clap(long)]
+        watch: bool,
+        /// Additionally write the variables set by the .envrc to this file, in the dotenv format
+        /// understood by docker-compose and similar tools.
+        #[clap(long, value_name = "PATH")]
+        env_file: Option<PathBuf>,
+        /// Also write the output of .envrc, both stdout and stderr, to this file. The file is
+        /// overwritten on every reload. Works together with '--quiet'.
+        #[clap(long, value_name = "PATH")]
+        capture_output: Option<PathBuf>,
+        /// Limit how the environment quickenv runs in can influence the .envrc: bash runs in
+        /// privileged mode ('-p'), which ignores BASH_ENV and functions exported by the parent
+        /// shell, and variables such as LD_PRELOAD are cleared.
+        ///
+        /// This is not a sandbox. The .envrc can still access the network, and read and write any
+        /// file your user can. bash's own restricted mode is not used, as it forbids changing
+        /// PATH, which nearly every .envrc does.
+        #[clap(long)]
+        restricted: bool,
+        /// Run the .envrc with stdin closed instead of connected to the terminal. Anything the
+        /// .envrc tries to read gets end-of-file right away instead of waiting for input, so
+        /// interactive prompts in .envrc files are not supported with this option.
+        #[clap(long)]
+        no_input: bool,
+        /// Print a JSON object summarizing the result instead of warnings about unshimmed
+        /// commands: whether it succeeded, how many variables changed, the cache path, how long
+        /// it took, and which commands are not shimmed yet. The output of .envrc goes to stderr
+        /// instead.
+        #[clap(long, conflicts_with_all = ["print", "watch"])]
+        json: bool,
+        /// Write the cache in the format of older quickenv versions, as 'KEY=VALUE' lines in
+        /// alphabetical order and without the header recording which .envrc it belongs to, for
+        /// tools that read cache files directly. quickenv reads both formats, but values
+        /// containing newlines may be misread from the old one.
+        #[clap(long, conflicts_with = "print")]
+        legacy_format: bool,
+        /// Fail with exit status 1 if the variables set by the .envrc differ from what was cached
+        /// before, and list the variables that changed. The cache is updated either way. Useful in
+        /// CI to check that a committed cache is up to date.
+        #[clap(long, conflicts_with_all = ["print", "watch", "json"])]
+        diff_exit_code: bool,
+        /// Evaluate this shell code instead of the contents of the .envrc, and print the
+        /// resulting variables like '--print'. Meant for debugging preludes and snippets. The
+        /// .envrc is still looked up, to determine the directory the code runs in.
+        #[clap(long, value_name = "CODE", conflicts_with_all = ["watch", "json", "diff_exit_code"])]
+        from: Option<String>,
+        /// With '--from', write the resulting variables to the cache of the .envrc instead of
+        /// printing them. Shims then apply them in place of the .envrc's own variables, so the
+        /// .envrc needs to be trusted.
+        #[clap(long, requires = "from", conflicts_with = "print")]
+        cache: bool,
+        /// Look for .envrc in this directory and its parents, instead of the current directory.
+        path: Option<PathBuf>,
+    },
+    /// Allow the .envrc in the current or parent directory to be executed by 'quickenv reload'.
+    ///
+    /// The .envrc needs to be trusted again after every change to it.
+    Trust,
+    /// Revoke trust for the .envrc in the current or parent directory.
+    Untrust,
+    /// Never load the .envrc in these directories (or anywhere below them) when running shims,
+    /// as if QUICKENV_NO_SHIM=1 was set there. Shims still run the program itself.
+    ///
+    /// Without arguments, the directory of the current .envrc is denied.
+    Deny {
+        /// Directories or glob patterns such as '/home/user/src/*/fixtures'. Relative paths are
+        /// relative to the current directory, and symlinks before the first glob are resolved.
+        patterns: Vec<String>,
+        /// Remove the given patterns from the list again.
+        #[clap(long, requires = "patterns")]
+        remove: bool,
+        /// Print all denied patterns.
+        #[clap(long, conflicts_with_all = ["patterns", "remove"])]
+        list: bool,
+    },
     /// Dump out cached environment variables.
     ///
     /// For example, use 'quickenv reload && eval "$(quickenv vars)"' to load the environment like
     /// direnv normally would.
-    Vars,
-    /// Create a new shim binary in ~/.quickenv/bin/.
+    ///
+    /// Exits with status 3 if there is no .envrc in the current or any parent directory, and with
+    /// status 4 if there is one, but 'quickenv reload' has not been run for it yet.
+    Vars {
+        /// Print the path of the .envrc the variables belong to (or of the file passed to
+        /// '--cache-file') as a comment in the first line.
+        #[clap(long)]
+        annotate: bool,
+        /// Terminate each variable with a NUL byte instead of a newline, for values that contain
+        /// newlines. Useful with 'xargs -0'.
+        #[clap(long, short = '0')]
+        null: bool,
+        /// Use the .envrc at the root of the enclosing git repository, instead of the nearest one.
+        #[clap(long)]
+        git_root: bool,
+        /// Read the variables from this cache file instead of the one belonging to the current
+        /// .envrc. Useful for inspecting the cache of another project.
+        #[clap(long, value_name = "PATH", conflicts_with = "git_root")]
+        cache_file: Option<PathBuf>,
+        /// Print variables sorted by name, or in the order they are stored in the cache ('none'),
+        /// which is the order bash reported them in.
+        #[clap(long, value_enum, default_value = "name")]
+        sort: VarsSort,
+        /// Only print variables whose value differs from the current environment, or which are not
+        /// set in it at all.
+        #[clap(long)]
+        changed_only: bool,
+    },
+    /// Show which .envrc applies to the current directory, and whether it has been loaded.
+    Status,
+    /// Check the setup of quickenv and of the current .envrc for common problems.
+    ///
+    /// Exits with status 1 if any problems were found.
+    Doctor,
+    /// Print which .envrc and cache file apply to a directory, without running anything.
+    ///
+    /// Unlike 'status', this never evaluates the .envrc and prints no warnings, so it is safe to
+    /// call from scripts and editor integrations.
+    Resolve {
+        /// Resolve for this directory instead of the current one.
+        #[clap(long, value_name = "DIR")]
+        dir: Option<PathBuf>,
+        /// Print a JSON object instead of one field per line.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Create a new shim binary in ~/.local/share/quickenv/bin/ (or QUICKENV_BIN_DIR).
     ///
     /// Executing that binary will run in the context of the nearest .envrc, as if it was activated
     /// by direnv.
@@ -63,17 +273,142 @@ enum Command {
         /// Disable confirmation prompts when running 'shim' without arguments.
         #[clap(long, short)]
         yes: bool,
+        /// Do not print informational messages on success. Errors and warnings are still printed.
+        #[clap(long, short)]
+        quiet: bool,
+        /// Only print the commands that would be shimmed when running 'shim' without arguments,
+        /// without prompting or creating any shims.
+        #[clap(long, conflicts_with = "commands")]
+        list_candidates: bool,
+        /// Point all existing shims at the currently running quickenv binary, for example after
+        /// upgrading or moving it.
+        #[clap(long, conflicts_with_all = ["commands", "list_candidates"])]
+        recreate: bool,
+        /// Create the shim even if another executable of the same name comes first on PATH, and
+        /// only warn about it. Useful for keeping the shim as a fallback.
+        #[clap(long)]
+        no_shadow_check: bool,
+        /// Do not check whether the new shims are found on PATH at all. Faster when shimming many
+        /// commands, and works when the bin directory is not on PATH yet.
+        #[clap(long)]
+        no_verify: bool,
+        /// Print which entries of PATH contain an executable of the same name as each shim, in
+        /// order of precedence, to show whether the shim takes effect.
+        #[clap(long)]
+        priority: bool,
+        /// Instead of informational messages, print one line per command to stdout, such as
+        /// 'created <name>', 'unchanged <name>' or 'skipped-self <name>'. A shim that is shadowed
+        /// by another executable on PATH is still kept, and reported as 'created-shadowed <name>'
+        /// or 'unchanged-shadowed <name>'.
+        #[clap(long)]
+        porcelain: bool,
+        /// Never recommend this command when running 'shim' without arguments. Can be repeated,
+        /// and adds to 'shim_exclude' in .quickenv.toml and QUICKENV_SHIM_EXCLUDE.
+        #[clap(long, value_name = "NAME", conflicts_with = "commands")]
+        exclude: Vec<String>,
+        /// Create a shim called NAME that runs COMMAND instead, for example 'python=python3'. Can
+        /// be repeated. Like all shims, the alias applies to every .envrc, and also to
+        /// 'quickenv exec NAME'. 'quickenv unshim NAME' removes it again.
+        ///
+        /// Without '--target', a shim whose name ends with '-raw' runs the command without that
+        /// suffix, and without loading the .envrc, unless there is a command with the full name.
+        /// Shimming 'make' and 'make-raw' allows comparing how make behaves with and without the
+        /// .envrc.
+        #[clap(
+            long,
+            value_name = "NAME=COMMAND",
+            value_parser = parse_shim_target,
+            conflicts_with_all = ["list_candidates", "recreate"]
+        )]
+        target: Vec<(String, String)>,
         /// The names of the commands to expose. If missing, quickenv will determine recommended
         /// commands itself and ask for confirmation.
         commands: Vec<String>,
     },
-    /// Remove a shim binary from ~/.quickenv/bin/.
+    /// Remove a shim binary from ~/.local/share/quickenv/bin/ (or QUICKENV_BIN_DIR).
     Unshim {
-        /// The names of the commands to remove.
+        /// Instead of informational messages, print one line per command to stdout, such as
+        /// 'removed <name>', 'not-found <name>' or 'skipped-self <name>', and 'cleared-cache
+        /// <path>' with '--also-clear-cache'.
+        #[clap(long)]
+        porcelain: bool,
+        /// Remove all shims that are broken, either because the quickenv binary they point to no
+        /// longer exists, or because the command cannot be found in the current directory anymore.
+        #[clap(long, conflicts_with = "commands")]
+        prune_broken: bool,
+        /// After removing the shims, also delete the cached variables of the .envrc in the current
+        /// directory, such that shims run without them until the next 'quickenv reload'. With
+        /// '--prune-broken', shims are still checked against the cache before it is deleted.
+        #[clap(long)]
+        also_clear_cache: bool,
+        /// The names of the commands to remove. Glob patterns such as 'cargo*' remove all
+        /// matching shims.
         commands: Vec<String>,
     },
+    /// List all shims in ~/.local/share/quickenv/bin/ (or QUICKENV_BIN_DIR).
+    List {
+        /// Print a JSON array describing each shim, including the program it resolves to in the
+        /// current directory.
+        #[clap(long)]
+        json: bool,
+    },
     /// Run a program with .envrc loaded without having to shim it.
+    ///
+    /// The .envrc is looked up from the current directory (or the git root with '--git-root'),
+    /// independently of where the program itself runs, which can be changed with '--cwd'.
     Exec {
+        /// Use the .envrc at the root of the enclosing git repository, instead of the nearest one.
+        #[clap(long)]
+        git_root: bool,
+        /// Run the program in this directory. This does not affect which .envrc is loaded.
+        #[clap(long, value_name = "DIR")]
+        cwd: Option<PathBuf>,
+        /// Keep quickenv's bin directory on the PATH of the program, so that it can call other
+        /// shimmed commands. The program itself is still looked up without shims.
+        #[clap(long)]
+        keep_shims: bool,
+        /// Run the command through a login shell ($QUICKENV_SHELL, or $SHELL, '-lc'), such that
+        /// shell functions and aliases work. The program name and arguments are joined with
+        /// spaces and interpreted by that shell, so they need to be quoted for it.
+        #[clap(long)]
+        shell: bool,
+        /// Run the .envrc again, and then the command in the same bash process, such that shell
+        /// functions defined by the .envrc can be called like programs. Unlike without this
+        /// option, the cached variables are not used, and the .envrc needs to be trusted. Output
+        /// of the .envrc itself goes to stderr.
+        #[clap(long, conflicts_with_all = ["shell", "keep_shims", "any"])]
+        login_shell_functions: bool,
+        /// Also find programs that are not on the PATH set up by the .envrc, by falling back to
+        /// the PATH quickenv was invoked with. By default, 'exec' runs exactly what a shim would.
+        /// The program still runs with all variables from the .envrc, including its PATH.
+        #[clap(long)]
+        any: bool,
+        /// Print how long quickenv took to determine the program and its environment from the
+        /// cache, and how long the program itself ran, as 'measure: resolve 1.234ms' and
+        /// 'measure: run 5.678ms' on stderr. With QUICKENV_SHIM_EXEC=1, only the former is
+        /// printed.
+        #[clap(long, conflicts_with = "login_shell_functions")]
+        measure: bool,
+        /// Fail with exit code 4 if the .envrc has not been loaded with 'quickenv reload' yet,
+        /// instead of running the program without its variables.
+        #[clap(long, conflicts_with = "login_shell_functions")]
+        from_cache: bool,
+        /// Debugging aid: leave quickenv's bin directory on the PATH of the program, without the
+        /// limit on nested shims that '--keep-shims' adds. Shims the program runs may end up
+        /// calling each other endlessly.
+        #[clap(long, hide = true, conflicts_with_all = ["login_shell_functions", "keep_shims"])]
+        no_path_strip: bool,
+        /// Only pass these variables from the .envrc to the program, a comma-separated list such
+        /// as 'A,B', to find out which of them matter. The .envrc's PATH is always passed as well,
+        /// unless '-PATH' is listed, in which case the program gets the PATH quickenv was invoked
+        /// with. The program is still looked up on the .envrc's PATH either way.
+        #[clap(
+            long,
+            value_name = "NAMES",
+            value_delimiter = ',',
+            conflicts_with = "login_shell_functions"
+        )]
+        vars: Option<Vec<String>>,
         program_name: OsString,
         #[clap(allow_hyphen_values = true, trailing_var_arg = true)]
         args: Vec<OsString>,
@@ -82,15 +417,150 @@ enum Command {
     ///
     /// This will error if the shim is not installed. Pass '--pretend-shimmed' to simulate what would
     /// happen anyway.
+    ///
+    /// With QUICKENV_NO_SHIM=1, shims don't load the .envrc, so this prints what the ambient PATH
+    /// resolves to instead, whether a shim is installed or not.
     Which {
-        /// The command name to look up.
-        program_name: OsString,
+        /// The command names to look up.
+        #[clap(required = true)]
+        program_names: Vec<OsString>,
 
         /// If quickenv does not have a shim under the given program name, this command errors by
         /// default. This check can be disabled using '--pretend-shimmed'
         #[clap(long)]
         pretend_shimmed: bool,
+
+        /// Print all matching programs on the PATH the shim would use, in order of precedence.
+        /// When looking up multiple commands, each line is prefixed with the command name.
+        #[clap(long)]
+        all: bool,
+
+        /// Print a JSON array with one object per command, containing the program name, the
+        /// program that would be launched ("winner"), and the list of candidates (all of them with
+        /// '--all').
+        #[clap(long)]
+        json: bool,
+    },
+    /// Inspect the cached environment variables of all projects.
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+    /// Print the location of the quickenv binary that shims point to.
+    SelfPath,
+    /// Print the line that adds the shim directory to PATH.
+    ///
+    /// Add 'eval "$(quickenv shellenv bash)"' to your .bashrc (or the equivalent for your shell)
+    /// instead of hardcoding the path.
+    Shellenv {
+        /// The shell to print the line for.
+        #[clap(value_enum)]
+        shell: ShellKind,
+    },
+    /// Print the version of quickenv. Include the output of '--verbose' in bug reports.
+    Version {
+        /// Also print how quickenv was built, where it stores its state, and which of the
+        /// programs it depends on could be found.
+        #[clap(long, short)]
+        verbose: bool,
+        /// Print a JSON object instead of one field per line. Implies '--verbose'.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Move shims, caches and trusted .envrc files from ~/.quickenv to the XDG directories.
+    ///
+    /// Shims are recreated to point to the current quickenv binary.
+    Migrate {
+        /// Only print what would be moved.
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Delete cached environment variables that were not reloaded for a while, no matter whether
+    /// their .envrc still exists. 'quickenv reload' recreates them as needed. Also deletes old
+    /// records of programs that shims found to be missing.
+    Gc {
+        /// Delete caches last reloaded longer ago than this, such as '30d', '12h', '45m' or '90s'.
+        #[clap(long, value_name = "AGE", value_parser = parse_age)]
+        max_age: Duration,
+        /// Only print what would be deleted.
+        #[clap(long)]
+        dry_run: bool,
     },
+    /// Print lists of names for shell completion plugins, one per line and without any other
+    /// output. Unlike the output of other commands, the format of these lists is kept stable.
+    #[clap(name = "__complete", hide = true)]
+    Complete {
+        #[clap(subcommand)]
+        command: CompleteCommand,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CompleteCommand {
+    /// The commands 'quickenv shim' without arguments would recommend in the current directory.
+    /// Empty if there is no .envrc or it was not loaded yet.
+    ShimCandidates,
+    /// The names of all existing shims.
+    InstalledShims,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CacheCommand {
+    /// Print how many env caches exist, how much space they take up, the oldest and newest of
+    /// them, and which .envrc files they belong to.
+    Stats {
+        /// Print a JSON object instead of one field per line.
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+/// Log target of debug output about shims and the programs they run, such that
+/// QUICKENV_LOG=quickenv::shim=debug shows only that. Other modules log under their module path,
+/// such as quickenv::core.
+const LOG_SHIM: &str = "quickenv::shim";
+/// Log target of debug output about running the .envrc during 'quickenv reload'.
+const LOG_RELOAD: &str = "quickenv::reload";
+
+/// Exit code for errors that don't fall into any of the categories below.
+const EXIT_FAILURE: i32 = 1;
+/// Exit code if there is no .envrc in the current or any parent directory.
+const EXIT_NO_ENVRC: i32 = 3;
+/// Exit code of 'quickenv vars' if the .envrc has never been loaded.
+const EXIT_NO_CACHE: i32 = 4;
+/// Exit code if the .envrc itself failed.
+const EXIT_ENVRC_FAILED: i32 = 5;
+/// Exit code if bash could not be found to run the .envrc with.
+const EXIT_NO_SHELL: i32 = 6;
+/// Exit code if the .envrc has not been trusted yet.
+const EXIT_UNTRUSTED: i32 = 7;
+/// Exit code if a file could not be accessed due to missing permissions.
+const EXIT_PERMISSION_DENIED: i32 = 8;
+
+/// Determine the exit code for an error, by the most specific cause that has a dedicated one.
+fn get_exit_code(error: &Error) -> i32 {
+    for cause in error.chain() {
+        let io_error = if let Some(e) = cause.downcast_ref::<core::Error>() {
+            match e {
+                core::Error::NoEnvrc => return EXIT_NO_ENVRC,
+                core::Error::EnvrcFailed(_) => return EXIT_ENVRC_FAILED,
+                core::Error::NoShell => return EXIT_NO_SHELL,
+                core::Error::Untrusted(_) => return EXIT_UNTRUSTED,
+                core::Error::Io(e) | core::Error::CurrentDir(e) => e,
+                _ => continue,
+            }
+        } else if let Some(e) = cause.downcast_ref::<io::Error>() {
+            e
+        } else {
+            continue;
+        };
+
+        if io_error.kind() == io::ErrorKind::PermissionDenied {
+            return EXIT_PERMISSION_DENIED;
+        }
+    }
+
+    EXIT_FAILURE
 }
 
 fn main() {
@@ -98,12 +568,15 @@ fn main() {
         Ok(()) => (),
         Err(e) => {
             log::error!("{:?}", e);
-            std::process::exit(1);
+            std::process::exit(get_exit_code(&e));
         }
     }
 }
 
 fn main_inner() -> Result<(), Error> {
+    let default_log_filter =
+        crate::config::project_log_level().unwrap_or_else(|| LevelFilter::Info.to_string());
+
     env_logger::Builder::new()
         .format(|buf, record| match record.level() {
             Level::Info => writeln!(buf, "{}", record.args()),
@@ -130,26 +603,160 @@ fn main_inner() -> Result<(), Error> {
                 record.args()
             ),
         })
-        .filter_level(LevelFilter::Info)
+        .parse_filters(&default_log_filter)
         .parse_env("QUICKENV_LOG")
+        .parse_filters(if ShimOpts::from_env().debug {
+            "debug"
+        } else {
+            ""
+        })
         .init();
 
     check_for_shim().context("failed to run shimmed command")?;
 
     let args = Args::parse();
+    args.color.apply();
 
-    crate::signals::set_ctrlc_handler()?;
+    crate::signals::set_ctrlc_handler();
 
     match args.subcommand {
-        Command::Reload => command_reload(),
-        Command::Vars => command_vars(),
-        Command::Shim { commands, yes } => command_shim(commands, yes),
-        Command::Unshim { commands } => command_unshim(commands),
-        Command::Exec { program_name, args } => command_exec(program_name, args),
-        Command::Which {
+        Command::Reload {
+            quiet,
+            git_root,
+            print,
+            watch,
+            env_file,
+            capture_output,
+            restricted,
+            no_input,
+            json,
+            legacy_format,
+            diff_exit_code,
+            from,
+            cache,
+            path,
+        } => command_reload(
+            ReloadOptions {
+                quiet,
+                print: print || (from.is_some() && !cache),
+                from,
+                env_file,
+                capture_output,
+                restricted,
+                no_input,
+                json,
+                legacy_format,
+            },
+            git_root,
+            path,
+            watch,
+            diff_exit_code,
+        ),
+        Command::Trust => command_trust(),
+        Command::Untrust => command_untrust(),
+        Command::Deny {
+            patterns,
+            remove,
+            list,
+        } => command_deny(patterns, remove, list),
+        Command::Vars {
+            annotate,
+            null,
+            git_root,
+            cache_file,
+            sort,
+            changed_only,
+        } => command_vars(annotate, null, git_root, cache_file, sort, changed_only),
+        Command::Status => command_status(),
+        Command::Doctor => command_doctor(),
+        Command::Resolve { dir, json } => command_resolve(dir, json),
+        Command::Shim {
+            list_candidates: true,
+            exclude,
+            ..
+        } => command_list_shim_candidates(&exclude),
+        Command::Shim {
+            recreate: true,
+            quiet,
+            porcelain,
+            ..
+        } => command_recreate_shims(quiet, porcelain),
+        Command::Shim {
+            commands,
+            yes,
+            quiet,
+            list_candidates: false,
+            recreate: false,
+            no_shadow_check,
+            no_verify,
+            priority,
+            porcelain,
+            exclude,
+            target,
+        } => command_shim(
+            commands,
+            ShimFlags {
+                yes,
+                quiet,
+                no_shadow_check,
+                no_verify,
+                priority,
+                porcelain,
+                exclude,
+                targets: target,
+            },
+        ),
+        Command::Unshim {
+            porcelain,
+            prune_broken,
+            also_clear_cache,
+            commands,
+        } => command_unshim(commands, porcelain, prune_broken, also_clear_cache),
+        Command::List { json } => command_list(json),
+        Command::Exec {
+            git_root,
+            cwd,
+            keep_shims,
+            shell,
+            login_shell_functions,
+            any,
+            measure,
+            from_cache,
+            no_path_strip,
+            vars,
             program_name,
+            args,
+        } => command_exec(
+            program_name,
+            args,
+            git_root,
+            shell,
+            login_shell_functions,
+            ExecOptions {
+                cwd,
+                keep_shims,
+                any,
+                measure,
+                from_cache,
+                no_path_strip,
+                vars,
+            },
+        ),
+        Command::Which {
+            program_names,
             pretend_shimmed,
-        } => command_which(program_name, pretend_shimmed),
+            all,
+            json,
+        } => command_which(program_names, pretend_shimmed, all, json),
+        Command::Cache {
+            command: CacheCommand::Stats { json },
+        } => command_cache_stats(json),
+        Command::SelfPath => command_self_path(),
+        Command::Complete { command } => command_complete(command),
+        Command::Shellenv { shell } => command_shellenv(shell),
+        Command::Version { verbose, json } => command_version(verbose, json),
+        Command::Migrate { dry_run } => command_migrate(dry_run),
+        Command::Gc { max_age, dry_run } => command_gc(max_age, dry_run),
     }
 }
 
@@ -162,13 +769,38 @@ enum ParseState {
     End,
 }
 
+/// Generate a token that is unique to one run of compute_envvars.
+///
+/// It is appended to the markers around each env dump, such that nothing the .envrc prints can be
+/// mistaken for a marker.
+fn generate_marker_nonce(seed: &Path) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(seed.as_os_str().as_bytes());
+    hasher.update(&process::id().to_le_bytes());
+    if let Ok(now) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        hasher.update(&now.as_nanos().to_le_bytes());
+    }
+    hex::encode(&hasher.finalize().as_bytes()[..16])
+}
+
 fn parse_env_diff<R: BufRead>(
     reader: R,
+    nonce: &str,
     mut script_output: impl FnMut(&[u8]) -> Result<(), Error>,
-) -> Result<(core::Env, core::Env), Error> {
+) -> Result<(core::Env, core::Env, Vec<OsString>), Error> {
+    // Markers start with a NUL byte, which can't be part of any variable. A value containing the
+    // marker's text therefore can't end a section early, even though it spans multiple lines.
+    let marker = |name: &str| format!("\0// {name} {nonce}").into_bytes();
+    let begin_before = marker("BEGIN QUICKENV-BEFORE");
+    let end_before = marker("END QUICKENV-BEFORE");
+    let begin_after = marker("BEGIN QUICKENV-AFTER");
+    let end_after = marker("END QUICKENV-AFTER");
+
     let mut parse_state = ParseState::PreBefore;
     let mut old_env = BTreeMap::new();
     let mut new_env = BTreeMap::new();
+    // the order in which variables are reported after running the .envrc, to keep in the cache
+    let mut new_env_order = Vec::new();
     let mut prev_var_name = None;
 
     for line in reader.split(b'\n') {
@@ -179,19 +811,19 @@ fn parse_env_diff<R: BufRead>(
         }
 
         match (parse_state, line) {
-            (ParseState::PreBefore, b"// BEGIN QUICKENV-BEFORE") => {
+            (ParseState::PreBefore, line) if line == begin_before => {
                 prev_var_name = None;
                 parse_state = ParseState::InBefore;
             }
-            (ParseState::InBefore, b"// END QUICKENV-BEFORE") => {
+            (ParseState::InBefore, line) if line == end_before => {
                 prev_var_name = None;
                 parse_state = ParseState::PreAfter;
             }
-            (ParseState::PreAfter, b"// BEGIN QUICKENV-AFTER") => {
+            (ParseState::PreAfter, line) if line == begin_after => {
                 prev_var_name = None;
                 parse_state = ParseState::InAfter;
             }
-            (ParseState::InAfter, b"// END QUICKENV-AFTER") => {
+            (ParseState::InAfter, line) if line == end_after => {
                 prev_var_name = None;
                 parse_state = ParseState::End;
             }
@@ -200,6 +832,9 @@ fn parse_env_diff<R: BufRead>(
             }
             (ParseState::InAfter, line) => {
                 core::parse_env_line(line, &mut new_env, &mut prev_var_name);
+                if line.contains(&b'=') {
+                    new_env_order.extend(prev_var_name.clone());
+                }
             }
             (_, _) => {
                 script_output(&raw_line)?;
@@ -207,29 +842,34 @@ fn parse_env_diff<R: BufRead>(
         }
     }
 
-    Ok((old_env, new_env))
+    // Without this, a .envrc killed halfway through would produce a cache with no variables at all.
+    if !matches!(parse_state, ParseState::End) {
+        anyhow::bail!(".envrc exited before its environment could be read, did it call exit?");
+    }
+
+    Ok((old_env, new_env, new_env_order))
 }
 
 #[test]
 fn test_parse_env_diff() {
-    let input = br#"
+    let input = b"
 some output 1
-// BEGIN QUICKENV-BEFORE
+\0// BEGIN QUICKENV-BEFORE abc
 hello=world
 bogus=wogus
-// END QUICKENV-BEFORE
+\0// END QUICKENV-BEFORE abc
 some output 2
-// BEGIN QUICKENV-AFTER
+\0// BEGIN QUICKENV-AFTER abc
 hello=world
 bogus=wogus
 2
 more=keys
-// END QUICKENV-AFTER
+\0// END QUICKENV-AFTER abc
 some output 3
-"#;
+";
 
     let mut output: Vec<Vec<u8>> = Vec::new();
-    let (old_env, new_env) = parse_env_diff(input.as_slice(), |line| {
+    let (old_env, new_env, new_env_order) = parse_env_diff(input.as_slice(), "abc", |line| {
         output.push(line.to_owned());
         Ok(())
     })
@@ -250,6 +890,10 @@ some output 3
             "more".into() => "keys".into(),
         ]
     );
+    assert_eq!(
+        new_env_order,
+        vec![OsString::from("hello"), "bogus".into(), "more".into()]
+    );
 
     assert_eq!(
         output,
@@ -262,18 +906,174 @@ some output 3
     );
 }
 
-fn compute_envvars(quickenv_home: &Path) -> Result<(), Error> {
+#[test]
+fn test_parse_env_diff_duplicate_keys() {
+    let input = b"\0// BEGIN QUICKENV-BEFORE abc
+hello=world
+\0// END QUICKENV-BEFORE abc
+\0// BEGIN QUICKENV-AFTER abc
+hello=first
+continued
+other=value
+hello=last
+\0// END QUICKENV-AFTER abc
+";
+
+    let (_, new_env, new_env_order) = parse_env_diff(input.as_slice(), "abc", |_| Ok(())).unwrap();
+    assert_eq!(
+        new_env,
+        maplit::btreemap![
+            "hello".into() => "last".into(),
+            "other".into() => "value".into(),
+        ]
+    );
+    assert_eq!(
+        new_env_order,
+        vec![OsString::from("hello"), "other".into(), "hello".into()]
+    );
+}
+
+#[test]
+fn test_parse_env_diff_missing_after() {
+    let input = b"\0// BEGIN QUICKENV-BEFORE abc
+hello=world
+\0// END QUICKENV-BEFORE abc
+\0// BEGIN QUICKENV-AFTER abc
+hello=world
+";
+
+    let result = parse_env_diff(input.as_slice(), "abc", |_| Ok(()));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_parse_env_diff_spoofed_markers() {
+    let input = b"// BEGIN QUICKENV-BEFORE
+\0// BEGIN QUICKENV-BEFORE abc
+hello=world
+\0// END QUICKENV-BEFORE abc
+// BEGIN QUICKENV-AFTER
+\0// END QUICKENV-AFTER xyz
+\0// BEGIN QUICKENV-AFTER abc
+hello=world
+// END QUICKENV-AFTER abc
+more=keys
+\0// END QUICKENV-AFTER abc
+";
+
+    let mut output: Vec<Vec<u8>> = Vec::new();
+    let (_old_env, new_env, _) = parse_env_diff(input.as_slice(), "abc", |line| {
+        output.push(line.to_owned());
+        Ok(())
+    })
+    .unwrap();
+
+    assert_eq!(
+        new_env,
+        maplit::btreemap![
+            // a marker without the leading NUL byte is part of the value
+            "hello".into() => "world\n// END QUICKENV-AFTER abc".into(),
+            "more".into() => "keys".into(),
+        ]
+    );
+
+    assert_eq!(
+        output,
+        vec![
+            b"// BEGIN QUICKENV-BEFORE".as_slice().to_owned(),
+            b"// BEGIN QUICKENV-AFTER".as_slice().to_owned(),
+            b"\0// END QUICKENV-AFTER xyz".as_slice().to_owned(),
+        ]
+    );
+}
+
+/// Options for 'quickenv reload' that affect how the .envrc is evaluated.
+#[derive(Default, Clone)]
+struct ReloadOptions {
+    /// Discard the output of the .envrc.
+    quiet: bool,
+    /// Print the resulting variables instead of writing the cache.
+    print: bool,
+    /// Code to evaluate instead of the .envrc.
+    from: Option<String>,
+    /// Also write the resulting variables to this file, in dotenv format.
+    env_file: Option<PathBuf>,
+    /// Also write the output of the .envrc to this file.
+    capture_output: Option<PathBuf>,
+    /// Run bash in privileged mode and without variables that inject code into it.
+    restricted: bool,
+    /// Run the .envrc without stdin.
+    no_input: bool,
+    /// Print a JSON summary instead of warnings about unshimmed commands.
+    json: bool,
+    /// Write the cache without a header.
+    legacy_format: bool,
+}
+
+/// Variables removed from the environment of the .envrc with 'reload --restricted', as they can
+/// make bash or any program it starts run additional code.
+const RESTRICTED_REMOVED_VARS: &[&str] = &[
+    "BASH_ENV",
+    "ENV",
+    "LD_PRELOAD",
+    "LD_LIBRARY_PATH",
+    "LD_AUDIT",
+    "DYLD_INSERT_LIBRARIES",
+    "DYLD_LIBRARY_PATH",
+];
+
+/// The code to run before each .envrc, which loads direnv's stdlib by default.
+fn get_prelude() -> Result<String, Error> {
+    match std::env::var("QUICKENV_PRELUDE") {
+        Ok(x) => Ok(x),
+        Err(_) => {
+            // Without this check, a missing direnv only shows up as the .envrc failing.
+            if which::which("direnv").is_err() {
+                anyhow::bail!(
+                    "direnv not found on PATH. Install it, or set QUICKENV_PRELUDE to something \
+                     that does not require it, such as QUICKENV_PRELUDE=''"
+                );
+            }
+            Ok(r#"eval "$(direnv stdlib)""#.to_owned())
+        }
+    }
+}
+
+fn compute_envvars(quickenv_home: &Path, options: &ReloadOptions) -> Result<(), Error> {
     let mut ctx = crate::core::resolve_envrc_context(quickenv_home)?;
-    std::fs::create_dir_all(&ctx.env_cache_dir).with_context(|| {
-        format!(
-            "failed to create cache directory at {}",
-            &ctx.env_cache_dir.display()
-        )
-    })?;
+
+    let envrc_contents = match options.from {
+        // The code was passed on the command line, and the .envrc itself does not run, so only
+        // printing the result needs no trust. Shims would apply a cached result in place of the
+        // .envrc's variables though, which must not happen for a project nobody trusted.
+        Some(ref code) if options.print => code.clone().into_bytes(),
+        _ => {
+            let mut envrc_contents = Vec::new();
+            ctx.envrc
+                .read_to_end(&mut envrc_contents)
+                .with_context(|| format!("failed to read {}", ctx.envrc_path.display()))?;
+            trust::ensure_trusted(quickenv_home, &ctx, &envrc_contents)?;
+            match options.from {
+                Some(ref code) => code.clone().into_bytes(),
+                None => envrc_contents,
+            }
+        }
+    };
+
+    if !options.print {
+        std::fs::create_dir_all(&ctx.env_cache_dir).with_context(|| {
+            format!(
+                "failed to create cache directory at {}",
+                &ctx.env_cache_dir.display()
+            )
+        })?;
+    }
     let mut temp_script = tempfile::NamedTempFile::new_in(&ctx.root)
         .with_context(|| format!("failed to create temporary file at {}", ctx.root.display()))?;
     let temp_script_path = temp_script.path().to_owned();
 
+    let nonce = generate_marker_nonce(&temp_script_path);
+
     let write_failure = || {
         format!(
             "failed to write to temporary file at {}",
@@ -281,156 +1081,834 @@ fn compute_envvars(quickenv_home: &Path) -> Result<(), Error> {
         )
     };
 
-    let prelude = std::env::var("QUICKENV_PRELUDE")
-        .unwrap_or_else(|_| r#"eval "$(direnv stdlib)""#.to_owned());
+    let prelude = get_prelude()?;
 
+    // With QUICKENV_NATIVE_BEFORE_ENV=1, the environment before running the .envrc is determined
+    // by quickenv instead of bash, saving one env dump. The BEFORE section is left empty then.
+    let native_before_env = std::env::var("QUICKENV_NATIVE_BEFORE_ENV").as_deref() == Ok("1");
+    let before_env_cmd = if native_before_env { "" } else { "command env" };
+
+    // The markers are emitted with 'command printf' rather than echo, such that neither echo's
+    // platform-specific quirks nor functions defined by the prelude or .envrc can mangle them.
     write!(
         temp_script,
         r##"
-echo '// BEGIN QUICKENV-BEFORE'
-env
-echo '// END QUICKENV-BEFORE'
+command printf '\0%s\n' '// BEGIN QUICKENV-BEFORE {nonce}'
+{before_env_cmd}
+command printf '\0%s\n' '// END QUICKENV-BEFORE {nonce}'
+__quickenv_root_{nonce}=$PWD
 {prelude}
 "##,
     )
     .with_context(write_failure)?;
 
-    io::copy(&mut ctx.envrc, &mut temp_script).with_context(write_failure)?;
+    temp_script
+        .write_all(&envrc_contents)
+        .with_context(write_failure)?;
 
     write!(
         temp_script,
         r##"
-echo '// BEGIN QUICKENV-AFTER'
-env
-echo '// END QUICKENV-AFTER'
+builtin cd -- "$__quickenv_root_{nonce}"
+command printf '\0%s\n' '// BEGIN QUICKENV-AFTER {nonce}'
+command env
+command printf '\0%s\n' '// END QUICKENV-AFTER {nonce}'
 "##
     )
     .with_context(write_failure)?;
 
-    signals::pass_control_to_shim();
+    let idle_timeout = get_idle_timeout()?;
 
-    let mut cmd = process::Command::new("bash")
-        .arg(&temp_script_path)
-        .env("QUICKENV_NO_SHIM", "1")
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::piped())
-        .current_dir(ctx.root)
-        .spawn()
-        .context("failed to spawn bash for running envrc")?;
+    signals::pass_control_to_shim();
 
-    let stdout_buf = BufReader::new(cmd.stdout.take().unwrap());
-    let (old_env, new_env) = parse_env_diff(stdout_buf, |line| {
-        io::stdout().write_all(line)?;
-        io::stdout().write_all(b"\n")?;
-        Ok(())
-    })
-    .context("failed to parse envrc output")?;
+    // split on whitespace only, options for bash are not expected to contain any
+    let bash_args = std::env::var("QUICKENV_BASH_ARGS").unwrap_or_default();
 
-    let status = cmd.wait().context("failed to wait for envrc subprocess")?;
+    let capture = match options.capture_output {
+        Some(ref path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            Some(Arc::new(Mutex::new(file)))
+        }
+        None => None,
+    };
 
-    if !status.success() {
-        Err(anyhow::anyhow!(".envrc exited with status {status}"))?;
+    let mut cmd = process::Command::new("bash");
+    let locale = get_pinned_locale();
+    if let Some(ref locale) = locale {
+        cmd.env("LC_ALL", locale);
     }
 
-    let mut env_cache =
-        BufWriter::new(std::fs::File::create(&ctx.env_cache_path).with_context(|| {
-            format!(
-                "failed to create envrc cache at {}",
-                &ctx.env_cache_path.display()
-            )
-        })?);
-
-    for (key, value) in new_env {
-        if old_env.get(&key) != Some(&value) {
-            env_cache.write_all(key.as_bytes())?;
-            env_cache.write_all(b"=")?;
-            env_cache.write_all(value.as_bytes())?;
-            env_cache.write_all(b"\n")?;
+    if options.restricted {
+        cmd.arg("-p");
+        for (key, _) in std::env::vars_os() {
+            let is_function = key.as_bytes().starts_with(b"BASH_FUNC_");
+            if is_function || RESTRICTED_REMOVED_VARS.iter().any(|x| key == *x) {
+                log::debug!(
+                    target: LOG_RELOAD,
+                    "removing {:?} from the environment of .envrc",
+                    key,
+                );
+                cmd.env_remove(key);
+            }
         }
     }
 
-    Ok(())
-}
+    if capture.is_some() {
+        cmd.stderr(Stdio::piped());
+    }
 
-fn get_missing_shims(
-    quickenv_home: &Path,
-    new_path_envvar: Option<&OsStr>,
-) -> Result<BTreeSet<String>, Error> {
-    let mut rv = BTreeSet::new();
-    let new_path_envvar = match new_path_envvar {
-        Some(x) => x,
-        None => return Ok(rv),
+    // With QUICKENV_PTY=1, the .envrc writes to a pseudo-terminal instead of a pipe, for tools
+    // that behave differently when their output is not a terminal.
+    let pty_master = if std::env::var("QUICKENV_PTY").as_deref() == Ok("1") {
+        let pty = open_raw_pty()?;
+        cmd.stdout(Stdio::from(pty.slave));
+        Some(pty.master)
+    } else {
+        cmd.stdout(Stdio::piped());
+        None
     };
 
-    let old_paths = std::env::var("PATH").context("failed to read PATH")?;
-    let old_paths = std::env::split_paths(&old_paths)
-        .map(|x| std::fs::canonicalize(&x).unwrap_or(x))
-        .collect::<BTreeSet<PathBuf>>();
+    let mut child = cmd
+        .args(bash_args.split_whitespace())
+        .arg(&temp_script_path)
+        .env("QUICKENV_NO_SHIM", "1")
+        .stdin(if options.no_input {
+            Stdio::null()
+        } else {
+            Stdio::inherit()
+        })
+        .current_dir(&ctx.root)
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::from(core::Error::NoShell),
+            _ => Error::from(e).context("failed to spawn bash for running envrc"),
+        })?;
+    // closes our copy of the pty's child side, without which reading it would never end
+    drop(cmd);
 
-    for directory in std::env::split_paths(new_path_envvar) {
-        let directory = std::fs::canonicalize(&directory).unwrap_or(directory);
-        if old_paths.contains(&directory) {
-            continue;
+    let stdout: Box<dyn Read + Send> = match pty_master {
+        Some(master) => Box::new(PtyReader(std::fs::File::from(master))),
+        None => Box::new(child.stdout.take().unwrap()),
+    };
+    let last_activity = Arc::new(Mutex::new(Instant::now()));
+    let stdout_buf = BufReader::new(ActivityReader {
+        inner: stdout,
+        last_activity: last_activity.clone(),
+    });
+
+    // stderr is only piped when capturing output, and then still forwarded to our own stderr
+    let stderr_thread = match (&capture, child.stderr.take()) {
+        (Some(capture), Some(stderr)) => {
+            let capture = capture.clone();
+            let stderr_buf = BufReader::new(ActivityReader {
+                inner: stderr,
+                last_activity: last_activity.clone(),
+            });
+            Some(std::thread::spawn(move || {
+                let mut result = Ok(());
+                // keep draining the pipe after a failed write, such that the .envrc doesn't block
+                for line in stderr_buf.split(b'\n') {
+                    let Ok(mut line) = line else { break };
+                    line.push(b'\n');
+                    let _ = io::stderr().write_all(&line);
+                    if result.is_ok() {
+                        result = capture.lock().unwrap().write_all(&line);
+                    }
+                }
+                result
+            }))
         }
+        _ => None,
+    };
 
-        match get_missing_shims_from_dir(quickenv_home, &directory, &mut rv) {
-            Ok(()) => (),
-            Err(e) => {
-                log::debug!("skipping over directory {:?}: {:?}", directory, e);
-                continue;
+    // Parse in a separate thread, such that we can give up on the .envrc if it hangs.
+    let (parse_result_tx, parse_result_rx) = mpsc::channel();
+    let ReloadOptions {
+        quiet, print, json, ..
+    } = *options;
+    std::thread::spawn(move || {
+        let result = parse_env_diff(stdout_buf, &nonce, |line| {
+            if let Some(ref capture) = capture {
+                let mut capture = capture.lock().unwrap();
+                capture.write_all(line)?;
+                capture.write_all(b"\n")?;
             }
-        }
-    }
 
-    Ok(rv)
-}
+            if quiet {
+                return Ok(());
+            }
 
-fn get_missing_shims_from_dir(
-    quickenv_home: &Path,
-    path: &Path,
-    rv: &mut BTreeSet<String>,
-) -> Result<(), Error> {
-    for entry in std::fs::read_dir(path)? {
-        let entry = entry?;
-        let metadata = entry.metadata()?;
-        if metadata.is_dir() {
-            // directories have the executable bit set, so we should skip them explicitly.
-            continue;
-        }
-        let permissions = metadata.permissions();
-        let is_executable = permissions.mode() & 0o111 != 0;
-        if !is_executable {
-            continue;
-        }
+            // with --print and --json, stdout is reserved for quickenv's own output
+            let mut output: Box<dyn Write> = if print || json {
+                Box::new(io::stderr())
+            } else {
+                Box::new(io::stdout())
+            };
+            output.write_all(line)?;
+            output.write_all(b"\n")?;
+            // show progress of long-running scripts right away, also when redirected to a file
+            output.flush()?;
+            Ok(())
+        });
+        let _ = parse_result_tx.send(result);
+    });
 
-        let entry_path = entry.path();
+    let parse_result = match idle_timeout {
+        None => parse_result_rx.recv().ok(),
+        Some(idle_timeout) => loop {
+            let idle = last_activity.lock().unwrap().elapsed();
+            if idle >= idle_timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                let error = anyhow::anyhow!(
+                    ".envrc printed nothing for {:?}, killed it (QUICKENV_IDLE_TIMEOUT)",
+                    idle_timeout
+                );
+                record_reload_failure(&ctx, options, &error);
+                return Err(error);
+            }
 
-        let filename = match entry_path.file_name().and_then(|x| x.to_str()) {
-            Some(x) => x,
-            None => continue,
-        };
+            match parse_result_rx.recv_timeout(idle_timeout - idle) {
+                Ok(result) => break Some(result),
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break None,
+            }
+        },
+    };
 
-        if !quickenv_home.join("bin").join(filename).exists() {
-            rv.insert(filename.to_owned());
-        }
-    }
+    let parse_result =
+        parse_result.ok_or_else(|| anyhow::anyhow!("envrc output parser crashed"))?;
 
-    Ok(())
-}
+    let status = child
+        .wait()
+        .context("failed to wait for envrc subprocess")?;
 
-fn command_reload() -> Result<(), Error> {
-    let quickenv_home = crate::core::get_quickenv_home()?;
-    let mut unshimmed_commands = CheckUnshimmedCommands::new(&quickenv_home)?;
-    unshimmed_commands.exclude_current()?;
-    compute_envvars(&quickenv_home)?;
-    unshimmed_commands.check_unshimmed_commands(false)?;
+    if let Some(stderr_thread) = stderr_thread {
+        stderr_thread
+            .join()
+            .map_err(|_| anyhow::anyhow!("envrc stderr forwarder crashed"))?
+            .with_context(|| {
+                format!(
+                    "failed to write to {}",
+                    options.capture_output.as_ref().unwrap().display()
+                )
+            })?;
+    }
 
-    Ok(())
-}
+    if !status.success() {
+        let error = core::Error::EnvrcFailed(status);
+        record_reload_failure(&ctx, options, &error);
+        return Err(error.into());
+    }
+
+    // checked after the exit status, such that a failing .envrc is reported as such
+    let (mut old_env, new_env, new_env_order) =
+        parse_result.context("failed to parse envrc output")?;
+
+    if native_before_env {
+        old_env = get_native_before_env(&ctx.root, &new_env, locale.as_deref());
+    }
+
+    let mut seen = BTreeSet::new();
+    let changed_env = new_env_order
+        .iter()
+        .filter(|key| seen.insert(*key))
+        .map(|key| (key, &new_env[key]))
+        .filter(|(key, _)| !IGNORED_VARS.iter().any(|x| key.as_os_str() == *x))
+        .filter(|(key, value)| old_env.get(*key) != Some(*value))
+        .map(|(key, value)| (key.as_os_str(), value.as_os_str()))
+        .collect::<Vec<_>>();
+
+    if let Some(ref env_file) = options.env_file {
+        let mut sorted_env = changed_env.clone();
+        sorted_env.sort();
+        std::fs::write(env_file, format_dotenv(sorted_env))
+            .with_context(|| format!("failed to write {}", env_file.display()))?;
+    }
+
+    if options.print {
+        write_vars(io::stdout().lock(), changed_env, b"\n")?;
+        return Ok(());
+    }
+
+    let env_cache = if options.legacy_format {
+        core::serialize_legacy_env_cache(changed_env)
+    } else {
+        core::serialize_env_cache(Some(&ctx.envrc_path), changed_env)
+    };
+
+    let _ = std::fs::remove_file(core::get_reload_failure_path(&ctx));
+
+    // keep the mtime of the cache stable if nothing changed
+    if std::fs::read(&ctx.env_cache_path).is_ok_and(|old_cache| old_cache == env_cache) {
+        log::debug!(target: LOG_RELOAD, "cache unchanged");
+        mark_reloaded(&ctx);
+        return Ok(());
+    }
+
+    let write_cache = || std::fs::write(&ctx.env_cache_path, &env_cache);
+    let mut result = write_cache();
+
+    // The cache directory may have been removed while the .envrc was running, for example by a
+    // concurrent cleanup. Create it again, but only try once more.
+    if matches!(&result, Err(e) if e.kind() == io::ErrorKind::NotFound) {
+        log::debug!(target: LOG_RELOAD, "cache directory disappeared, creating it again");
+        std::fs::create_dir_all(&ctx.env_cache_dir).with_context(|| {
+            format!(
+                "failed to create cache directory at {}",
+                &ctx.env_cache_dir.display()
+            )
+        })?;
+        result = write_cache();
+    }
+
+    result.with_context(|| {
+        format!(
+            "failed to write envrc cache at {}",
+            &ctx.env_cache_path.display()
+        )
+    })?;
+    mark_reloaded(&ctx);
+    Ok(())
+}
+
+/// Variables that are never cached, because they only describe the state of the bash process that
+/// ran the .envrc. The .envrc may 'cd' around, after which quickenv changes back to the project
+/// root.
+const IGNORED_VARS: &[&str] = &["PWD", "OLDPWD"];
+
+/// Format variables for a dotenv file.
+///
+/// Values are single-quoted where possible, as those are taken literally by all dotenv parsers.
+/// Values containing single quotes or newlines are double-quoted and escaped instead.
+fn format_dotenv<'a>(envvars: impl IntoIterator<Item = (&'a OsStr, &'a OsStr)>) -> Vec<u8> {
+    let mut rv = Vec::new();
+    for (key, value) in envvars {
+        let value = value.as_bytes();
+        rv.extend_from_slice(key.as_bytes());
+        rv.push(b'=');
+        if !value.contains(&b'\'') && !value.contains(&b'\n') {
+            rv.push(b'\'');
+            rv.extend_from_slice(value);
+            rv.push(b'\'');
+        } else {
+            rv.push(b'"');
+            for &c in value {
+                match c {
+                    b'\n' => rv.extend_from_slice(b"\\n"),
+                    b'\\' | b'"' | b'$' | b'`' => rv.extend_from_slice(&[b'\\', c]),
+                    _ => rv.push(c),
+                }
+            }
+            rv.push(b'"');
+        }
+        rv.push(b'\n');
+    }
+    rv
+}
+
+#[test]
+fn test_format_dotenv() {
+    assert_eq!(
+        format_dotenv([
+            (OsStr::new("A"), OsStr::new("with space $HOME")),
+            (OsStr::new("B"), OsStr::new("it's \"quoted\" $HOME")),
+            (OsStr::new("C"), OsStr::new("line1\nline2")),
+        ]),
+        b"A='with space $HOME'\nB=\"it's \\\"quoted\\\" \\$HOME\"\nC=\"line1\\nline2\"\n"
+    );
+}
+
+/// Reconstruct the environment bash would report before running the .envrc, without asking bash.
+///
+/// This is the environment of quickenv itself, plus the variables that quickenv and bash set when
+/// starting the script.
+fn get_native_before_env(root: &Path, new_env: &core::Env, locale: Option<&OsStr>) -> core::Env {
+    let mut env: core::Env = std::env::vars_os().collect();
+    env.insert("QUICKENV_NO_SHIM".into(), "1".into());
+    if let Some(locale) = locale {
+        env.insert("LC_ALL".into(), locale.to_owned());
+    }
+
+    // bash sets PWD to the directory it was started in, and increments SHLVL
+    env.insert("PWD".into(), root.as_os_str().to_owned());
+    let shlvl = env
+        .get(OsStr::new("SHLVL"))
+        .and_then(|x| x.to_str()?.parse::<i64>().ok())
+        .unwrap_or(0);
+    env.insert("SHLVL".into(), (shlvl + 1).to_string().into());
+
+    // '_' is set by bash to the path of the last command, which is 'env' both times
+    match new_env.get(OsStr::new("_")) {
+        Some(value) => env.insert("_".into(), value.clone()),
+        None => env.remove(OsStr::new("_")),
+    };
+
+    env
+}
+
+/// Read QUICKENV_LOCALE, the locale to evaluate the .envrc with instead of the user's.
+fn get_pinned_locale() -> Option<OsString> {
+    std::env::var_os("QUICKENV_LOCALE").filter(|x| !x.is_empty())
+}
+
+/// Record why running the .envrc failed next to its cache. Shims tell the user about it, as they'd
+/// otherwise silently keep using the old cache.
+fn record_reload_failure(
+    ctx: &core::EnvrcContext,
+    options: &ReloadOptions,
+    error: &impl std::fmt::Display,
+) {
+    if !options.print {
+        let _ = std::fs::write(core::get_reload_failure_path(ctx), error.to_string());
+    }
+}
+
+/// Record that the .envrc was just reloaded successfully, such that 'quickenv gc' keeps its cache
+/// even if the cache itself was not rewritten.
+fn mark_reloaded(ctx: &core::EnvrcContext) {
+    let _ = std::fs::File::create(core::get_last_reload_path(ctx))
+        .and_then(|file| file.set_modified(std::time::SystemTime::now()));
+}
+
+/// Read QUICKENV_IDLE_TIMEOUT, the number of seconds the .envrc may go without printing anything
+/// before it is considered hung.
+fn get_idle_timeout() -> Result<Option<Duration>, Error> {
+    match std::env::var("QUICKENV_IDLE_TIMEOUT") {
+        Ok(value) if !value.is_empty() => {
+            let timeout = value
+                .parse()
+                .ok()
+                .filter(|x: &f64| *x > 0.0)
+                .and_then(|x| Duration::try_from_secs_f64(x).ok())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("invalid QUICKENV_IDLE_TIMEOUT {value:?}, expected seconds")
+                })?;
+            Ok(Some(timeout))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Read QUICKENV_MISSING_CACHE_TTL, the number of seconds for which shims remember that their
+/// program could not be found. Zero, the default, disables this.
+fn get_missing_cache_ttl() -> Result<Duration, Error> {
+    match std::env::var("QUICKENV_MISSING_CACHE_TTL") {
+        Ok(value) if !value.is_empty() => value
+            .parse()
+            .ok()
+            .and_then(|x: f64| Duration::try_from_secs_f64(x).ok())
+            .ok_or_else(|| {
+                anyhow::anyhow!("invalid QUICKENV_MISSING_CACHE_TTL {value:?}, expected seconds")
+            }),
+        _ => Ok(Duration::ZERO),
+    }
+}
+
+/// Open a pseudo-terminal for the output of the .envrc. It is put into raw mode, such that the
+/// output arrives unchanged instead of with newlines translated to "\r\n".
+fn open_raw_pty() -> Result<nix::pty::OpenptyResult, Error> {
+    use nix::sys::termios;
+
+    let pty = nix::pty::openpty(None::<&nix::pty::Winsize>, None::<&termios::Termios>)
+        .context("failed to open a pseudo-terminal (QUICKENV_PTY)")?;
+    let mut settings =
+        termios::tcgetattr(&pty.slave).context("failed to configure pseudo-terminal")?;
+    termios::cfmakeraw(&mut settings);
+    termios::tcsetattr(&pty.slave, termios::SetArg::TCSANOW, &settings)
+        .context("failed to configure pseudo-terminal")?;
+    Ok(pty)
+}
+
+/// The parent side of a pseudo-terminal. Once the .envrc and everything it started closed the
+/// other side, reading it fails with EIO on Linux, which is treated as the end of output.
+struct PtyReader(std::fs::File);
+
+impl Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.raw_os_error() == Some(nix::errno::Errno::EIO as i32) => Ok(0),
+            result => result,
+        }
+    }
+}
+
+/// Wrapper around the .envrc's stdout that records when it last produced output.
+struct ActivityReader<R> {
+    inner: R,
+    last_activity: Arc<Mutex<Instant>>,
+}
+
+impl<R: Read> Read for ActivityReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            *self.last_activity.lock().unwrap() = Instant::now();
+        }
+        Ok(n)
+    }
+}
+
+/// Determine the commands that the .envrc's PATH exposes, and which are not shimmed yet.
+///
+/// A command counts as exposed if the .envrc's PATH would run a different executable for it than
+/// the ambient PATH. That is the case for all executables in directories that are new to PATH,
+/// and for executables in directories that the .envrc moved ahead of other directories containing
+/// an executable of the same name.
+fn get_missing_shims(
+    quickenv_home: &Path,
+    new_path_envvar: Option<&OsStr>,
+) -> Result<BTreeSet<String>, Error> {
+    let mut rv = BTreeSet::new();
+    let new_path_envvar = match new_path_envvar {
+        Some(x) => x,
+        None => return Ok(rv),
+    };
+
+    // Canonicalizing is comparatively expensive and PATH entries are frequently duplicated
+    // between the ambient and the .envrc PATH, so remember results for the duration of this call.
+    let mut canonical_paths = BTreeMap::new();
+    let mut canonicalize = |path: PathBuf| -> PathBuf {
+        canonical_paths
+            .entry(path)
+            .or_insert_with_key(|path| std::fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+            .clone()
+    };
+
+    let old_paths = std::env::var("PATH").context("failed to read PATH")?;
+    let old_paths = std::env::split_paths(&old_paths)
+        .map(&mut canonicalize)
+        .collect::<Vec<PathBuf>>();
+
+    let bin_dir = core::get_bin_dir(quickenv_home);
+    let mut skipped_entries = 0;
+    let mut preceding_directories = BTreeSet::new();
+
+    for directory in std::env::split_paths(new_path_envvar) {
+        let directory = canonicalize(directory);
+        let shadowed_directories = match old_paths.iter().position(|x| *x == directory) {
+            // Directories that came before this one on the ambient PATH, but don't anymore. Only
+            // executables of the same name as in those directories change their meaning.
+            Some(old_index) => {
+                let demoted = old_paths[..old_index]
+                    .iter()
+                    .filter(|x| !preceding_directories.contains(*x))
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                if demoted.is_empty() {
+                    preceding_directories.insert(directory);
+                    continue;
+                }
+
+                Some(demoted)
+            }
+            None => None,
+        };
+
+        let result = get_missing_shims_from_dir(
+            &bin_dir,
+            &directory,
+            shadowed_directories.as_deref(),
+            &mut rv,
+        );
+        preceding_directories.insert(directory.clone());
+
+        match result {
+            Ok(()) => (),
+            Err(e) => {
+                let reason = match e.kind() {
+                    io::ErrorKind::NotFound => "does not exist",
+                    io::ErrorKind::NotADirectory => "not a directory",
+                    io::ErrorKind::PermissionDenied => "permission denied",
+                    _ => "unreadable",
+                };
+                log::debug!(
+                    target: LOG_SHIM,
+                    "skipping over PATH entry {} ({}): {}",
+                    directory.display(),
+                    reason,
+                    e,
+                );
+                skipped_entries += 1;
+            }
+        }
+    }
+
+    if skipped_entries > 0 {
+        log::debug!(target: LOG_SHIM, "skipped {} inaccessible PATH entries", skipped_entries);
+    }
+
+    Ok(rv)
+}
+
+/// Collect executables in `path` that are not shimmed yet. If `shadowed_directories` is given,
+/// only executables that also exist in one of those directories are collected.
+fn get_missing_shims_from_dir(
+    bin_dir: &Path,
+    path: &Path,
+    shadowed_directories: Option<&[PathBuf]>,
+    rv: &mut BTreeSet<String>,
+) -> io::Result<()> {
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            // directories have the executable bit set, so we should skip them explicitly.
+            continue;
+        }
+        let permissions = metadata.permissions();
+        let is_executable = permissions.mode() & 0o111 != 0;
+        if !is_executable {
+            continue;
+        }
+
+        let entry_path = entry.path();
+
+        let filename = match entry_path.file_name().and_then(|x| x.to_str()) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        if let Some(shadowed_directories) = shadowed_directories {
+            if !shadowed_directories
+                .iter()
+                .any(|x| x.join(filename).exists())
+            {
+                continue;
+            }
+        }
+
+        if !bin_dir.join(filename).exists() {
+            rv.insert(filename.to_owned());
+        }
+    }
+
+    Ok(())
+}
+
+fn command_reload(
+    mut options: ReloadOptions,
+    git_root: bool,
+    path: Option<PathBuf>,
+    watch: bool,
+    diff_exit_code: bool,
+) -> Result<(), Error> {
+    if let Some(env_file) = options.env_file.take() {
+        // resolve relative to where we were invoked from, before changing directories below
+        let cwd = std::env::current_dir().context("failed to get current directory")?;
+        options.env_file = Some(cwd.join(env_file));
+    }
+
+    if let Some(capture_output) = options.capture_output.take() {
+        let cwd = std::env::current_dir().context("failed to get current directory")?;
+        options.capture_output = Some(cwd.join(capture_output));
+    }
+
+    if let Some(path) = path {
+        std::env::set_current_dir(&path)
+            .with_context(|| format!("failed to change directory to {}", path.display()))?;
+    }
+
+    if git_root {
+        change_to_git_root()?;
+    }
+
+    let quickenv_home = crate::core::get_quickenv_home()?;
+
+    if watch {
+        return watch_envrc(&quickenv_home, &options);
+    }
+
+    if diff_exit_code {
+        return reload_diff(&quickenv_home, &options);
+    }
+
+    reload_once(&quickenv_home, &options)
+}
+
+/// Reload, then fail if any variable differs from the previous cache.
+fn reload_diff(quickenv_home: &Path, options: &ReloadOptions) -> Result<(), Error> {
+    let ctx = resolve_envrc_context(quickenv_home)?;
+    let envvars_before = core::get_envvars(&ctx).ok().flatten().unwrap_or_default();
+    reload_once(quickenv_home, options)?;
+    let envvars_after = core::get_envvars(&ctx).ok().flatten().unwrap_or_default();
+
+    let changed = get_changed_envvars(&envvars_before, &envvars_after);
+    if !changed.is_empty() {
+        anyhow::bail!(
+            "the cache of {} was out of date, these variables changed: {}",
+            ctx.envrc_path.display(),
+            changed
+                .iter()
+                .map(|key| key.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+fn reload_once(quickenv_home: &Path, options: &ReloadOptions) -> Result<(), Error> {
+    if options.print {
+        // nothing is written to the cache, so there are no new commands to warn about either
+        return compute_envvars(quickenv_home, options);
+    }
+
+    if options.json {
+        return reload_json(quickenv_home, options);
+    }
+
+    let mut unshimmed_commands = CheckUnshimmedCommands::new(quickenv_home)?;
+    unshimmed_commands.exclude_current()?;
+    compute_envvars(quickenv_home, options)?;
+    unshimmed_commands.check_unshimmed_commands(false)?;
+
+    Ok(())
+}
+
+/// Output of 'quickenv reload --json'.
+#[derive(serde::Serialize)]
+struct ReloadOutput {
+    success: bool,
+    error: Option<String>,
+    /// How many variables differ from the previous cache.
+    changed: usize,
+    /// How many variables are cached now.
+    variables: usize,
+    cache: String,
+    duration_ms: u128,
+    unshimmed: Vec<String>,
+}
+
+fn reload_json(quickenv_home: &Path, options: &ReloadOptions) -> Result<(), Error> {
+    let start = Instant::now();
+    let ctx = resolve_envrc_context(quickenv_home)?;
+    let envvars_before = core::get_envvars(&ctx).ok().flatten().unwrap_or_default();
+
+    let mut result = compute_envvars(quickenv_home, options);
+    let duration = start.elapsed();
+
+    let envvars_after = core::get_envvars(&ctx).ok().flatten().unwrap_or_default();
+    // Failures end up in the JSON output like those of the reload itself.
+    let unshimmed = match result {
+        Ok(()) => {
+            let path_envvar = envvars_after.get(OsStr::new("PATH"));
+            match get_missing_shims(quickenv_home, path_envvar.map(OsString::as_os_str)) {
+                Ok(unshimmed) => unshimmed.into_iter().collect(),
+                Err(e) => {
+                    result = Err(e);
+                    Vec::new()
+                }
+            }
+        }
+        Err(_) => Vec::new(),
+    };
+
+    let output = ReloadOutput {
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|e| format!("{e:#}")),
+        changed: count_changed_envvars(&envvars_before, &envvars_after),
+        variables: envvars_after.len(),
+        cache: ctx.env_cache_path.to_string_lossy().into_owned(),
+        duration_ms: duration.as_millis(),
+        unshimmed,
+    };
+
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &output)?;
+    writeln!(stdout)?;
+    result
+}
+
+/// Count the variables that were added, removed or changed between two versions of a cache.
+fn count_changed_envvars(before: &core::Env, after: &core::Env) -> usize {
+    get_changed_envvars(before, after).len()
+}
+
+/// The names of the variables that were added, removed or changed between two versions of a
+/// cache, sorted.
+fn get_changed_envvars<'a>(before: &'a core::Env, after: &'a core::Env) -> Vec<&'a OsString> {
+    before
+        .keys()
+        .chain(after.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|key| before.get(*key) != after.get(*key))
+        .collect()
+}
+
+/// How often 'reload --watch' checks for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Reload whenever any of the files that affect the result of 'quickenv reload' change.
+///
+/// This polls instead of using filesystem notifications, as there are only a handful of small
+/// files to check.
+fn watch_envrc(quickenv_home: &Path, options: &ReloadOptions) -> Result<(), Error> {
+    let ctx = resolve_envrc_context(quickenv_home)?;
+    let watched_paths = [
+        ctx.envrc_path.clone(),
+        ctx.root.join(".envrc.local"),
+        trust::get_trust_path(quickenv_home, &ctx),
+    ];
+    let read_watched_paths = || -> Vec<Option<Vec<u8>>> {
+        watched_paths
+            .iter()
+            .map(|path| std::fs::read(path).ok())
+            .collect()
+    };
+
+    log::info!(
+        "Watching {} for changes. Press Ctrl-C to stop.",
+        style(ctx.envrc_path.display()).cyan()
+    );
+
+    loop {
+        let state = read_watched_paths();
+        let envvars_before = core::get_envvars(&ctx).ok().flatten().unwrap_or_default();
+
+        match reload_once(quickenv_home, options) {
+            Ok(()) if !options.print => {
+                let envvars_after = core::get_envvars(&ctx).ok().flatten().unwrap_or_default();
+                let changes = count_changed_envvars(&envvars_before, &envvars_after);
+                log::info!("Reloaded, {} variables changed.", style(changes).green());
+            }
+            Ok(()) => (),
+            Err(e) => log::error!("{:?}", e),
+        }
+
+        // Running the .envrc passed Ctrl-C on to it, take it back for waiting
+        signals::take_control_from_shim();
+
+        while read_watched_paths() == state {
+            std::thread::sleep(WATCH_INTERVAL);
+        }
+    }
+}
+
+/// Change into the root of the enclosing git repository, such that resolving the .envrc from the
+/// current directory picks up the one at the repository root.
+fn change_to_git_root() -> Result<(), Error> {
+    let git_root = core::find_git_root()?;
+    if !git_root.join(".envrc").is_file() {
+        return Err(anyhow::anyhow!(
+            "failed to find .envrc at the git repository root {}",
+            git_root.display()
+        ));
+    }
+
+    log::debug!("using .envrc at git repository root {}", git_root.display());
+    std::env::set_current_dir(&git_root)
+        .with_context(|| format!("failed to change directory to {}", git_root.display()))?;
+    Ok(())
+}
 
 enum CheckUnshimmedCommands<'a> {
     Enabled {
-        ctx: core::EnvrcContext,
+        ctx: Box<core::EnvrcContext>,
         quickenv_home: &'a Path,
         old_missing_shims: BTreeSet<String>,
     },
@@ -439,15 +1917,23 @@ enum CheckUnshimmedCommands<'a> {
 
 impl<'a> CheckUnshimmedCommands<'a> {
     fn new(quickenv_home: &'a Path) -> Result<Self, Error> {
-        if std::env::var("QUICKENV_NO_SHIM_WARNINGS").unwrap_or_default() == "1" {
-            Ok(CheckUnshimmedCommands::Disabled)
-        } else {
-            Ok(CheckUnshimmedCommands::Enabled {
-                ctx: resolve_envrc_context(quickenv_home)?,
-                quickenv_home,
-                old_missing_shims: BTreeSet::new(),
-            })
+        let global_setting = std::env::var("QUICKENV_NO_SHIM_WARNINGS").ok();
+        if global_setting.as_deref() == Some("1") {
+            return Ok(CheckUnshimmedCommands::Disabled);
+        }
+
+        let ctx = resolve_envrc_context(quickenv_home)?;
+
+        if global_setting.is_none() && ctx.config.shim_warnings == Some(false) {
+            log::debug!(target: LOG_SHIM, "shim warnings disabled by project config");
+            return Ok(CheckUnshimmedCommands::Disabled);
         }
+
+        Ok(CheckUnshimmedCommands::Enabled {
+            ctx: Box::new(ctx),
+            quickenv_home,
+            old_missing_shims: BTreeSet::new(),
+        })
     }
 
     fn exclude_current(&mut self) -> Result<(), Error> {
@@ -519,52 +2005,642 @@ impl<'a> CheckUnshimmedCommands<'a> {
     }
 }
 
-fn command_vars() -> Result<(), Error> {
+fn command_trust() -> Result<(), Error> {
     let quickenv_home = crate::core::get_quickenv_home()?;
-    let ctx = resolve_envrc_context(&quickenv_home)?;
+    let mut ctx = resolve_envrc_context(&quickenv_home)?;
+    let mut envrc_contents = Vec::new();
+    ctx.envrc
+        .read_to_end(&mut envrc_contents)
+        .with_context(|| format!("failed to read {}", ctx.envrc_path.display()))?;
+    trust::trust(&quickenv_home, &ctx, &envrc_contents)?;
+
+    log::info!(
+        "Trusted {}. Use {} to load it.",
+        style(ctx.envrc_path.display()).cyan(),
+        style("'quickenv reload'").magenta()
+    );
+    Ok(())
+}
+
+fn command_deny(patterns: Vec<String>, remove: bool, list: bool) -> Result<(), Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let mut deny_list = deny::read_deny_list(&quickenv_home)?;
 
-    if let Some(envvars) = core::get_envvars(&ctx)? {
-        for (k, v) in envvars {
-            io::stdout().write_all(k.as_bytes())?;
-            io::stdout().write_all(b"=")?;
-            io::stdout().write_all(v.as_bytes())?;
-            io::stdout().write_all(b"\n")?;
+    if list {
+        for pattern in deny_list {
+            println!("{}", pattern);
         }
+        return Ok(());
+    }
 
-        Ok(())
+    let cwd = std::env::current_dir().context("failed to get current directory")?;
+    let patterns = if patterns.is_empty() {
+        vec![resolve_envrc_context(&quickenv_home)?.root]
     } else {
-        log::error!(
-            "Run {} first to generate envvars",
-            style("'quickenv reload'").magenta()
-        );
-        std::process::exit(1);
+        patterns
+            .iter()
+            .map(|pattern| deny::canonicalize_pattern(&normalize_path(&cwd.join(pattern))))
+            .collect()
+    };
+
+    for pattern in patterns {
+        let pattern = pattern.to_string_lossy().into_owned();
+        if remove {
+            if deny_list.contains(&pattern) {
+                deny_list.retain(|x| *x != pattern);
+                log::info!("Removed {} from the deny list.", style(&pattern).cyan());
+            } else {
+                log::warn!("{} was not denied.", pattern);
+            }
+        } else if deny_list.contains(&pattern) {
+            log::warn!("{} is already denied.", pattern);
+        } else {
+            log::info!(
+                "Denied {}. Shims will not load any .envrc there.",
+                style(&pattern).cyan()
+            );
+            deny_list.push(pattern);
+        }
     }
+
+    deny::write_deny_list(&quickenv_home, &deny_list)?;
+    Ok(())
 }
 
-fn command_shim(mut commands: Vec<String>, yes: bool) -> Result<(), Error> {
-    let quickenv_home = crate::core::get_quickenv_home()?;
-    let bin_dir = quickenv_home.join("bin/");
+/// Remove '.' and '..' from a path without touching the filesystem, as it may contain glob
+/// patterns.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut rv = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => (),
+            std::path::Component::ParentDir => {
+                rv.pop();
+            }
+            component => rv.push(component),
+        }
+    }
+    rv
+}
+
+fn command_untrust() -> Result<(), Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let ctx = resolve_envrc_context(&quickenv_home)?;
+
+    if trust::untrust(&quickenv_home, &ctx)? {
+        log::info!("Untrusted {}.", style(ctx.envrc_path.display()).cyan());
+    } else {
+        log::warn!("{} was not trusted.", ctx.envrc_path.display());
+    }
+    Ok(())
+}
+
+fn command_vars(
+    annotate: bool,
+    null: bool,
+    git_root: bool,
+    cache_file: Option<PathBuf>,
+    sort: VarsSort,
+    changed_only: bool,
+) -> Result<(), Error> {
+    let terminator: &[u8] = if null { b"\0" } else { b"\n" };
+    let prepare = |mut envvars: core::EnvList| {
+        if sort == VarsSort::Name {
+            envvars.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        if changed_only {
+            envvars.retain(|(key, value)| std::env::var_os(key).as_ref() != Some(value));
+        }
+        envvars
+    };
+
+    if let Some(cache_file) = cache_file {
+        let envvars = core::read_env_cache(&cache_file)
+            .with_context(|| format!("failed to read cache file {}", cache_file.display()))?;
+        let envvars = prepare(envvars);
+
+        if annotate {
+            io::stdout().write_all(b"# ")?;
+            io::stdout().write_all(cache_file.as_os_str().as_bytes())?;
+            io::stdout().write_all(terminator)?;
+        }
+
+        write_vars(
+            io::stdout().lock(),
+            envvars.iter().map(|(k, v)| (k.as_os_str(), v.as_os_str())),
+            terminator,
+        )?;
+        return Ok(());
+    }
+
+    if git_root {
+        change_to_git_root()?;
+    }
+
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let ctx = resolve_envrc_context(&quickenv_home)?;
+
+    if let Some(envvars) = core::get_envvars_ordered(&ctx)? {
+        let envvars = prepare(envvars);
+        if annotate {
+            io::stdout().write_all(b"# ")?;
+            io::stdout().write_all(ctx.envrc_path.as_os_str().as_bytes())?;
+            io::stdout().write_all(terminator)?;
+        }
+
+        write_vars(
+            io::stdout().lock(),
+            envvars.iter().map(|(k, v)| (k.as_os_str(), v.as_os_str())),
+            terminator,
+        )?;
+
+        Ok(())
+    } else {
+        log::error!(
+            "Run {} first to generate envvars",
+            style("'quickenv reload'").magenta()
+        );
+        std::process::exit(EXIT_NO_CACHE);
+    }
+}
+
+/// Print variables as `KEY=VALUE`, in the format of 'quickenv vars'.
+fn write_vars<'a>(
+    mut output: impl Write,
+    envvars: impl IntoIterator<Item = (&'a OsStr, &'a OsStr)>,
+    terminator: &[u8],
+) -> io::Result<()> {
+    for (k, v) in envvars {
+        output.write_all(k.as_bytes())?;
+        output.write_all(b"=")?;
+        output.write_all(v.as_bytes())?;
+        output.write_all(terminator)?;
+    }
+
+    Ok(())
+}
+
+fn command_status() -> Result<(), Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let ctx = resolve_envrc_context(&quickenv_home)?;
+
+    println!("{} {}", style(".envrc:").cyan(), ctx.envrc_path.display());
+
+    if ctx.env_cache_path.exists() {
+        println!(
+            "{} {}",
+            style("cache:").cyan(),
+            ctx.env_cache_path.display()
+        );
+    } else {
+        println!(
+            "{} {} (missing, run {})",
+            style("cache:").cyan(),
+            ctx.env_cache_path.display(),
+            style("'quickenv reload'").magenta()
+        );
+    }
+
+    println!("{} {}", style("key:").cyan(), ctx.key);
+
+    Ok(())
+}
+
+/// Collects the results of the checks in 'quickenv doctor'.
+#[derive(Default)]
+struct DoctorReport {
+    problems: usize,
+}
+
+impl DoctorReport {
+    fn ok(&mut self, message: impl std::fmt::Display) {
+        println!("{} {}", style("ok:").green(), message);
+    }
+
+    fn problem(&mut self, message: impl std::fmt::Display) {
+        self.problems += 1;
+        println!("{} {}", style("problem:").red(), message);
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        match self.problems {
+            0 => Ok(()),
+            1 => Err(anyhow::anyhow!("found 1 problem")),
+            n => Err(anyhow::anyhow!("found {n} problems")),
+        }
+    }
+}
+
+fn command_doctor() -> Result<(), Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let bin_dir = core::get_bin_dir(&quickenv_home);
+    let mut report = DoctorReport::default();
+
+    match which::which("bash") {
+        Ok(path) => report.ok(format_args!("bash found at {}", path.display())),
+        Err(_) => report.problem("bash not found on PATH, it is required to run .envrc"),
+    }
+
+    if std::env::var_os("QUICKENV_PRELUDE").is_none() {
+        match which::which("direnv") {
+            Ok(path) => report.ok(format_args!("direnv found at {}", path.display())),
+            Err(_) => report.problem(
+                "direnv not found on PATH. Install it, or set QUICKENV_PRELUDE to something that \
+                 does not require it",
+            ),
+        }
+    }
+
+    let path_envvar = std::env::var_os("PATH").unwrap_or_default();
+    if std::env::split_paths(&path_envvar).any(|x| x == bin_dir) {
+        report.ok(format_args!("{} is on PATH", bin_dir.display()));
+    } else {
+        report.problem(format_args!(
+            "{} is not on PATH, so shims can't be found",
+            bin_dir.display()
+        ));
+    }
+
+    for shim in list_shims(&bin_dir)? {
+        if !shim.target.exists() {
+            report.problem(format_args!(
+                "shim {} is broken, {} is missing. Run 'quickenv shim --recreate'",
+                shim.name.to_string_lossy(),
+                shim.target.display()
+            ));
+            continue;
+        }
+
+        let shim_path = bin_dir.join(&shim.name);
+        if let Ok(effective_path) = which::which(&shim.name) {
+            if effective_path != shim_path {
+                report.problem(format_args!(
+                    "shim {} is shadowed by {}",
+                    shim.name.to_string_lossy(),
+                    effective_path.display()
+                ));
+                if let Some(hint) = get_path_order_hint(&bin_dir, &effective_path) {
+                    println!("  {hint}");
+                }
+            }
+        }
+    }
+
+    let mut ctx = match resolve_envrc_context(&quickenv_home) {
+        Ok(ctx) => ctx,
+        Err(core::Error::NoEnvrc) => {
+            report.ok("no .envrc in the current directory, skipping checks for it");
+            return report.finish();
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut envrc_contents = Vec::new();
+    ctx.envrc
+        .read_to_end(&mut envrc_contents)
+        .with_context(|| format!("failed to read {}", ctx.envrc_path.display()))?;
+    if trust::is_trusted(&quickenv_home, &ctx, &envrc_contents)? {
+        report.ok(format_args!("{} is trusted", ctx.envrc_path.display()));
+    } else {
+        report.problem(format_args!(
+            "{} is not trusted. Review it, then run 'quickenv trust'",
+            ctx.envrc_path.display()
+        ));
+    }
+
+    let envvars = match core::get_envvars(&ctx) {
+        Ok(Some(envvars)) => envvars,
+        Ok(None) => {
+            report.problem("the .envrc has not been loaded yet. Run 'quickenv reload'");
+            return report.finish();
+        }
+        Err(e) => {
+            report.problem(format_args!("{e}. Run 'quickenv reload'"));
+            return report.finish();
+        }
+    };
+
+    if let Some(failure) = core::get_reload_failure(&ctx) {
+        report.problem(format_args!(
+            "the last reload failed ({}), the cache is outdated",
+            failure.trim()
+        ));
+    }
+
+    if !core::is_cache_fresh(&ctx) {
+        report.problem("the .envrc changed since it was last loaded. Run 'quickenv reload'");
+    }
+
+    // Shims don't check this themselves, to stay fast. A missing directory usually means that a
+    // toolchain was uninstalled or moved.
+    let missing_dirs: Vec<_> = envvars
+        .get(OsStr::new("PATH"))
+        .map(std::env::split_paths)
+        .into_iter()
+        .flatten()
+        .filter(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .collect();
+    if missing_dirs.is_empty() {
+        report.ok("all directories on the cached PATH exist");
+    }
+    for dir in missing_dirs {
+        report.problem(format_args!(
+            "{} is on the cached PATH, but does not exist anymore. Run 'quickenv reload'",
+            dir.display()
+        ));
+    }
+
+    report.finish()
+}
+
+/// Output of 'quickenv resolve'.
+#[derive(serde::Serialize)]
+struct ResolveOutput {
+    envrc: String,
+    root: String,
+    cache: String,
+    /// The name of the cache file, which only depends on the path of the .envrc.
+    key: String,
+    cache_exists: bool,
+    /// Whether the cache was written after the .envrc was last modified.
+    cache_fresh: bool,
+}
+
+fn command_resolve(dir: Option<PathBuf>, json: bool) -> Result<(), Error> {
+    if let Some(dir) = dir {
+        std::env::set_current_dir(&dir)
+            .with_context(|| format!("failed to change directory to {}", dir.display()))?;
+    }
+
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let ctx = resolve_envrc_context(&quickenv_home)?;
+
+    let cache_exists = ctx.env_cache_path.exists();
+    let cache_fresh = core::is_cache_fresh(&ctx);
+
+    let output = ResolveOutput {
+        envrc: ctx.envrc_path.to_string_lossy().into_owned(),
+        root: ctx.root.to_string_lossy().into_owned(),
+        cache: ctx.env_cache_path.to_string_lossy().into_owned(),
+        key: ctx.key,
+        cache_exists,
+        cache_fresh,
+    };
+
+    let mut stdout = io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &output)?;
+        writeln!(stdout)?;
+    } else {
+        writeln!(stdout, "envrc: {}", output.envrc)?;
+        writeln!(stdout, "root: {}", output.root)?;
+        writeln!(stdout, "cache: {}", output.cache)?;
+        writeln!(stdout, "key: {}", output.key)?;
+        writeln!(stdout, "cache_exists: {}", output.cache_exists)?;
+        writeln!(stdout, "cache_fresh: {}", output.cache_fresh)?;
+    }
+
+    Ok(())
+}
+
+/// Explain how to reorder PATH such that shims in `bin_dir` take precedence over `shadowing_path`.
+///
+/// Returns None if either directory can't be found on PATH.
+fn get_path_order_hint(bin_dir: &Path, shadowing_path: &Path) -> Option<String> {
+    let shadowing_dir = shadowing_path.parent()?;
+    let path_envvar = std::env::var_os("PATH")?;
+
+    let entries: Vec<_> = std::env::split_paths(&path_envvar).collect();
+    let bin_dir_index = entries.iter().position(|x| is_same_dir(x, bin_dir))?;
+    let shadowing_index = entries.iter().position(|x| is_same_dir(x, shadowing_dir))?;
+    if shadowing_index > bin_dir_index {
+        return None;
+    }
+
+    Some(format!(
+        "{} is entry {} on your PATH, but {} comes earlier as entry {}. Move {} before it, for \
+         example by adding 'export PATH=\"{}:$PATH\"' at the end of your shell's config file.",
+        style(bin_dir.display()).cyan(),
+        bin_dir_index + 1,
+        style(shadowing_dir.display()).magenta(),
+        shadowing_index + 1,
+        style(bin_dir.display()).cyan(),
+        bin_dir.display(),
+    ))
+}
+
+/// Whether two paths refer to the same directory, also when spelled differently.
+fn is_same_dir(a: &Path, b: &Path) -> bool {
+    a == b
+        || matches!(
+            (std::fs::canonicalize(a), std::fs::canonicalize(b)),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
+
+/// For 'quickenv shim --priority', print all entries of PATH that contain an executable called
+/// `command`, and whether the shim comes first.
+fn print_path_priority(bin_dir: &Path, command: &str) {
+    let path_envvar = std::env::var_os("PATH").unwrap_or_default();
+    let mut shim_index = None;
+    let mut found = Vec::new();
+
+    for (i, entry) in std::env::split_paths(&path_envvar).enumerate() {
+        let is_bin_dir = is_same_dir(&entry, bin_dir);
+        if is_bin_dir && shim_index.is_none() {
+            shim_index = Some(found.len());
+        }
+
+        let is_executable = std::fs::metadata(entry.join(command))
+            .is_ok_and(|x| x.is_file() && x.permissions().mode() & 0o111 != 0);
+        if is_executable {
+            found.push((i + 1, entry, is_bin_dir));
+        }
+    }
+
+    log::info!(
+        "{} is found in these entries of PATH:",
+        style(command).cyan()
+    );
+    for (position, entry, is_bin_dir) in &found {
+        let note = if *is_bin_dir { " (quickenv shim)" } else { "" };
+        log::info!("  {}: {}{}", position, entry.display(), note);
+    }
+
+    match shim_index {
+        None => log::warn!(
+            "{} is not on PATH, so the shim has no effect",
+            style(bin_dir.display()).cyan()
+        ),
+        Some(0) => (),
+        Some(_) => log::warn!(
+            "the shim for {} comes after other executables of the same name, so it has no effect",
+            style(command).cyan()
+        ),
+    }
+}
+
+/// Create a symlink at `path` pointing to `target`, replacing whatever is at `path`.
+///
+/// The symlink is created under a temporary name and then renamed over `path`, so that shims
+/// running concurrently never observe the command as missing.
+fn replace_symlink(target: &Path, path: &Path) -> io::Result<()> {
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(format!(".quickenv-tmp-{}", process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let _ = std::fs::remove_file(&tmp_path);
+    symlink(target, &tmp_path)?;
+    std::fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp_path);
+    })
+}
+
+/// Commands that auto-shimming never recommends unless QUICKENV_SHIM_EXCLUDE says otherwise,
+/// because running them with a project's environment is rarely intended and potentially dangerous.
+const DEFAULT_SHIM_EXCLUDE: &[&str] = &["sudo", "su", "doas", "rm"];
+
+/// Determine the commands that auto-shimming would create shims for.
+///
+/// Commands in `exclude`, in the project's `shim_exclude`, and in QUICKENV_SHIM_EXCLUDE (or
+/// DEFAULT_SHIM_EXCLUDE if that is unset) are left out.
+fn get_shim_candidates(
+    quickenv_home: &Path,
+    exclude: &[String],
+) -> Result<(core::EnvrcContext, Vec<String>), Error> {
+    let ctx = resolve_envrc_context(quickenv_home)?;
+    let envvars = match crate::core::get_envvars(&ctx)? {
+        Some(x) => x,
+        None => {
+            log::error!(
+                "Run {} first to generate envvars",
+                style("'quickenv reload'").magenta()
+            );
+            std::process::exit(1);
+        }
+    };
+    let candidates = filter_shim_candidates(quickenv_home, &ctx, &envvars, exclude)?;
+    Ok((ctx, candidates))
+}
+
+/// The commands exposed by the given variables of the .envrc that are not shimmed yet, except for
+/// excluded ones.
+fn filter_shim_candidates(
+    quickenv_home: &Path,
+    ctx: &core::EnvrcContext,
+    envvars: &core::Env,
+    exclude: &[String],
+) -> Result<Vec<String>, Error> {
+    let path_envvar = envvars.get(OsStr::new("PATH")).map(OsString::as_os_str);
+    let global_exclude: Vec<String> = match std::env::var("QUICKENV_SHIM_EXCLUDE") {
+        Ok(value) => value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        Err(_) => DEFAULT_SHIM_EXCLUDE.iter().map(|&x| x.to_owned()).collect(),
+    };
+
+    let candidates = get_missing_shims(quickenv_home, path_envvar)?
+        .into_iter()
+        .filter(|command| {
+            let excluded = exclude
+                .iter()
+                .chain(ctx.config.shim_exclude.iter().flatten())
+                .chain(&global_exclude)
+                .any(|x| x == command);
+            if excluded {
+                log::debug!(target: LOG_SHIM, "not recommending excluded command {command}");
+            }
+            !excluded
+        })
+        .collect();
+
+    Ok(candidates)
+}
+
+fn command_list_shim_candidates(exclude: &[String]) -> Result<(), Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let (_ctx, candidates) = get_shim_candidates(&quickenv_home, exclude)?;
+
+    let stdout = console::Term::stdout();
+    if stdout.is_term() {
+        grid::print_as_grid_to(&stdout, &candidates)?;
+    } else {
+        for command in &candidates {
+            println!("{command}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Flags for 'quickenv shim'.
+#[derive(Default)]
+struct ShimFlags {
+    /// Skip the confirmation prompt when shimming recommended commands.
+    yes: bool,
+    /// Do not print informational messages on success.
+    quiet: bool,
+    /// Only warn if the new shim is shadowed by another executable.
+    no_shadow_check: bool,
+    /// Skip looking up the new shims on PATH entirely.
+    no_verify: bool,
+    /// Print the PATH entries containing each command.
+    priority: bool,
+    /// Print machine-readable results instead of informational messages.
+    porcelain: bool,
+    /// Commands to leave out when shimming recommended commands.
+    exclude: Vec<String>,
+    /// Shims to create that run a differently named command.
+    targets: Vec<(String, String)>,
+}
+
+/// Parse the argument of 'quickenv shim --target'.
+fn parse_shim_target(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, target)) if !name.is_empty() && !target.is_empty() => {
+            if name.contains('/') || target.contains('/') {
+                return Err("NAME and COMMAND must be command names, not paths".to_owned());
+            }
+            Ok((name.to_owned(), target.to_owned()))
+        }
+        _ => Err(format!("expected NAME=COMMAND, got {value:?}")),
+    }
+}
+
+fn command_shim(mut commands: Vec<String>, flags: ShimFlags) -> Result<(), Error> {
+    let ShimFlags {
+        yes,
+        quiet,
+        no_shadow_check,
+        no_verify,
+        priority,
+        porcelain,
+        exclude,
+        targets,
+    } = flags;
+
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let bin_dir = core::get_bin_dir(&quickenv_home);
+
+    for (name, _) in &targets {
+        if !commands.contains(name) {
+            commands.push(name.clone());
+        }
+    }
 
     let auto = commands.is_empty();
 
     if auto {
-        let ctx = resolve_envrc_context(&quickenv_home)?;
-        let envvars = match crate::core::get_envvars(&ctx)? {
-            Some(x) => x,
-            None => {
-                log::error!(
-                    "Run {} first to generate envvars",
-                    style("'quickenv reload'").magenta()
-                );
-                std::process::exit(1);
-            }
-        };
-        let path_envvar = envvars.get(OsStr::new("PATH")).map(OsString::as_os_str);
-        commands = get_missing_shims(&quickenv_home, path_envvar)?
-            .into_iter()
-            .collect();
+        let ctx;
+        (ctx, commands) = get_shim_candidates(&quickenv_home, &exclude)?;
 
-        if !commands.is_empty() {
+        // With --quiet and --yes there's nobody to show the candidates to.
+        let show_candidates = !(quiet && yes);
+
+        if !commands.is_empty() && show_candidates {
             eprintln!(
                 "Found these unshimmed commands in your {}:",
                 style(".envrc").cyan()
@@ -592,10 +2668,13 @@ fn command_shim(mut commands: Vec<String>, yes: bool) -> Result<(), Error> {
             eprintln!("Outside, they will run normally.");
 
             if !yes {
-                let answer = dialoguer::Confirm::new()
-                    .with_prompt(style("Continue?").red().to_string())
-                    .default(true)
-                    .interact()?;
+                let answer = {
+                    let _guard = signals::PromptGuard::new();
+                    dialoguer::Confirm::new()
+                        .with_prompt(style("Continue?").red().to_string())
+                        .default(true)
+                        .interact()?
+                };
 
                 if !answer {
                     std::process::exit(1);
@@ -608,114 +2687,1123 @@ fn command_shim(mut commands: Vec<String>, yes: bool) -> Result<(), Error> {
 
     std::fs::create_dir_all(&bin_dir)?;
 
-    let self_binary = which::which("quickenv")?;
+    let shim_target = get_shim_target()?;
 
     let mut changes = 0;
 
     for command in &commands {
         if command == "quickenv" {
             log::warn!("not shimming own binary");
+            if porcelain {
+                println!("skipped-self {}", command);
+            }
             continue;
         }
 
+        if let Some((_, target)) = targets.iter().find(|(name, _)| name == command) {
+            alias::set_alias(&quickenv_home, command, target)?;
+        } else if let Some(old_target) = alias::get_alias(&quickenv_home, command)? {
+            // shimming without '--target' means the shim should run the command of its own name
+            alias::remove_alias(&quickenv_home, command)?;
+            if !quiet && !porcelain {
+                log::info!(
+                    "{} no longer runs {}.",
+                    style(command).cyan(),
+                    style(old_target).magenta()
+                );
+            }
+        }
+
         let command_path = bin_dir.join(command);
 
-        let was_there = std::fs::remove_file(&command_path).is_ok();
-        symlink(&self_binary, &command_path).with_context(|| {
+        let was_there = std::fs::symlink_metadata(&command_path).is_ok();
+        replace_symlink(&shim_target, &command_path).with_context(|| {
             format!(
                 "failed to symlink {} to {}",
-                self_binary.display(),
+                shim_target.display(),
                 command_path.display()
             )
         })?;
 
-        if !was_there {
+        let status = if was_there {
+            "unchanged"
+        } else {
             changes += 1;
+            "created"
+        };
+
+        if priority {
+            print_path_priority(&bin_dir, command);
+        }
+
+        if no_verify {
+            if porcelain {
+                println!("{} {}", status, command);
+            }
+            continue;
         }
 
         let effective_command_path = which::which(command).with_context(|| {
             format!(
                 "failed to find command {} after shimming. Are you sure that {} is on your PATH?",
-                bin_dir.display(),
-                command
+                command,
+                bin_dir.display()
             )
         })?;
 
-        if effective_command_path != command_path {
-            log::error!(
-                "{} is shadowed by an executable of the same name at {}",
-                style(command_path.display()).cyan(),
-                style(effective_command_path.display()).magenta(),
-            );
-            std::process::exit(1);
+        if effective_command_path != command_path {
+            if porcelain {
+                // the shim exists either way, it just does not take effect
+                println!("{}-shadowed {}", status, command);
+            }
+
+            let hint = get_path_order_hint(&bin_dir, &effective_command_path);
+
+            if no_shadow_check {
+                log::warn!(
+                    "{} is shadowed by an executable of the same name at {}",
+                    style(command_path.display()).cyan(),
+                    style(effective_command_path.display()).magenta(),
+                );
+                if let Some(hint) = hint {
+                    log::warn!("{}", hint);
+                }
+                continue;
+            }
+
+            log::error!(
+                "{} is shadowed by an executable of the same name at {}",
+                style(command_path.display()).cyan(),
+                style(effective_command_path.display()).magenta(),
+            );
+            if let Some(hint) = hint {
+                log::error!("{}", hint);
+            }
+            std::process::exit(1);
+        }
+
+        if porcelain {
+            println!("{} {}", status, command);
+        }
+    }
+
+    if quiet || porcelain {
+        return Ok(());
+    }
+
+    if changes == 0 {
+        log::info!("created {} new shims.", style("no").red());
+    } else {
+        log::info!(
+            "Created {} new shims in {}.",
+            style(changes).green(),
+            style(bin_dir.display()).cyan(),
+        );
+        log::info!(
+            "Use {} to remove them again.",
+            style("'quickenv unshim <command>'").magenta(),
+        );
+    }
+
+    if auto {
+        log::info!(
+            "Use {} to run additional commands with {} enabled.",
+            style("'quickenv shim <command>'").magenta(),
+            style(".envrc").cyan()
+        );
+    }
+
+    Ok(())
+}
+
+fn command_recreate_shims(quiet: bool, porcelain: bool) -> Result<(), Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let bin_dir = core::get_bin_dir(&quickenv_home);
+    let shim_target = get_shim_target()?;
+
+    let mut changes = 0;
+    let mut unchanged = 0;
+
+    for shim in list_shims(&bin_dir)? {
+        if shim.target == shim_target {
+            unchanged += 1;
+            if porcelain {
+                println!("unchanged {}", shim.name.to_string_lossy());
+            }
+            continue;
+        }
+
+        let command_path = bin_dir.join(&shim.name);
+        replace_symlink(&shim_target, &command_path).with_context(|| {
+            format!(
+                "failed to symlink {} to {}",
+                shim_target.display(),
+                command_path.display()
+            )
+        })?;
+
+        changes += 1;
+        if porcelain {
+            println!("refreshed {}", shim.name.to_string_lossy());
+        }
+    }
+
+    if !quiet && !porcelain {
+        log::info!(
+            "Refreshed {} shims in {}, {} were already up to date.",
+            style(changes).green(),
+            style(bin_dir.display()).cyan(),
+            style(unchanged).green(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Determine the canonical path of the running quickenv binary, to tell whether some file is
+/// quickenv itself.
+fn get_self_binary() -> Result<PathBuf, Error> {
+    let current_exe = std::env::current_exe().context("failed to determine own binary")?;
+    std::fs::canonicalize(&current_exe)
+        .with_context(|| format!("failed to canonicalize {}", current_exe.display()))
+}
+
+/// Determine the path that shims are symlinked to, which is quickenv as it was invoked.
+///
+/// Package managers such as Homebrew or nix install a symlink to a versioned path. Pointing shims
+/// at where that symlink resolves to would break all of them on the next upgrade.
+fn get_shim_target() -> Result<PathBuf, Error> {
+    let self_binary = get_self_binary()?;
+    let invoked_as = std::env::args_os().next().and_then(|argv0| {
+        if argv0.as_bytes().contains(&b'/') {
+            std::path::absolute(argv0).ok()
+        } else {
+            which::which(argv0).ok()
+        }
+    });
+
+    // argv[0] is up to whoever started quickenv, so it may not point to quickenv at all
+    Ok(invoked_as
+        .filter(|path| std::fs::canonicalize(path).is_ok_and(|x| x == self_binary))
+        .unwrap_or(self_binary))
+}
+
+fn command_self_path() -> Result<(), Error> {
+    println!("{}", get_shim_target()?.display());
+    Ok(())
+}
+
+fn command_shellenv(shell: ShellKind) -> Result<(), Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    // get_bin_dir has a trailing slash, which would look odd in PATH
+    let bin_dir: PathBuf = crate::core::get_bin_dir(&quickenv_home)
+        .components()
+        .collect();
+    let bin_dir = bin_dir.to_string_lossy();
+
+    match shell {
+        ShellKind::Bash | ShellKind::Zsh => {
+            let mut escaped = String::new();
+            for c in bin_dir.chars() {
+                if matches!(c, '\\' | '"' | '$' | '`') {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            println!("export PATH=\"{}:$PATH\"", escaped);
+        }
+        ShellKind::Fish => {
+            let mut escaped = String::new();
+            for c in bin_dir.chars() {
+                if matches!(c, '\\' | '\'') {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            println!("fish_add_path --move --path '{}'", escaped);
+        }
+    }
+
+    Ok(())
+}
+
+/// Output of 'quickenv version --verbose'.
+#[derive(serde::Serialize)]
+struct VersionOutput {
+    version: &'static str,
+    git_sha: &'static str,
+    rustc: &'static str,
+    target: &'static str,
+    quickenv_home: Option<String>,
+    bin_dir: Option<String>,
+    env_cache_dir: Option<String>,
+    /// The shell 'quickenv exec --shell' would use.
+    shell: Option<String>,
+    bash: Option<String>,
+    direnv: Option<String>,
+}
+
+fn command_version(verbose: bool, json: bool) -> Result<(), Error> {
+    let version = env!("CARGO_PKG_VERSION");
+    if !verbose && !json {
+        println!("quickenv {version}");
+        return Ok(());
+    }
+
+    let quickenv_home = core::get_quickenv_home().ok();
+    let display = |path: PathBuf| path.to_string_lossy().into_owned();
+    let find = |program: &str| which::which(program).ok().map(display);
+
+    let output = VersionOutput {
+        version,
+        git_sha: env!("QUICKENV_GIT_SHA"),
+        rustc: env!("QUICKENV_RUSTC_VERSION"),
+        target: env!("QUICKENV_TARGET"),
+        bin_dir: quickenv_home.as_deref().map(core::get_bin_dir).map(display),
+        env_cache_dir: quickenv_home
+            .as_deref()
+            .map(core::get_env_cache_dir)
+            .map(display),
+        quickenv_home: quickenv_home.map(display),
+        shell: std::env::var_os("QUICKENV_SHELL")
+            .or_else(|| std::env::var_os("SHELL"))
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string_lossy().into_owned()),
+        bash: find("bash"),
+        direnv: find("direnv"),
+    };
+
+    let mut stdout = io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &output)?;
+        writeln!(stdout)?;
+        return Ok(());
+    }
+
+    let or_missing = |x: &Option<String>| x.clone().unwrap_or_else(|| "not found".to_owned());
+    writeln!(stdout, "quickenv {}", output.version)?;
+    writeln!(stdout, "git_sha: {}", output.git_sha)?;
+    writeln!(stdout, "rustc: {}", output.rustc)?;
+    writeln!(stdout, "target: {}", output.target)?;
+    writeln!(
+        stdout,
+        "quickenv_home: {}",
+        or_missing(&output.quickenv_home)
+    )?;
+    writeln!(stdout, "bin_dir: {}", or_missing(&output.bin_dir))?;
+    writeln!(
+        stdout,
+        "env_cache_dir: {}",
+        or_missing(&output.env_cache_dir)
+    )?;
+    writeln!(stdout, "shell: {}", or_missing(&output.shell))?;
+    writeln!(stdout, "bash: {}", or_missing(&output.bash))?;
+    writeln!(stdout, "direnv: {}", or_missing(&output.direnv))?;
+    Ok(())
+}
+
+fn command_migrate(dry_run: bool) -> Result<(), Error> {
+    if std::env::var_os("QUICKENV_HOME").is_some() {
+        return Err(anyhow::anyhow!(
+            "QUICKENV_HOME is set, so neither ~/.quickenv nor the XDG directories are used"
+        ));
+    }
+
+    let home = std::env::var_os("HOME").ok_or(core::Error::NoQuickenvHome)?;
+    let legacy_home = core::get_legacy_quickenv_home(&home);
+    if !legacy_home.exists() {
+        log::info!(
+            "Nothing to migrate, {} does not exist.",
+            style(legacy_home.display()).cyan()
+        );
+        return Ok(());
+    }
+
+    let new_home = core::get_xdg_quickenv_home(&home);
+    let mut dirs = vec![
+        (legacy_home.join("envs"), core::get_xdg_env_cache_dir(&home)),
+        (legacy_home.join("trusted"), new_home.join("trusted")),
+        (legacy_home.join("aliases"), new_home.join("aliases")),
+        (legacy_home.join("config"), new_home.join("config")),
+    ];
+
+    // a custom bin directory stays where it is
+    let migrate_bin_dir = std::env::var_os("QUICKENV_BIN_DIR").is_none_or(|x| x.is_empty());
+    if migrate_bin_dir {
+        dirs.push((legacy_home.join("bin"), new_home.join("bin")));
+    }
+
+    let shim_target = get_shim_target()?;
+    let mut changes = 0;
+
+    for (old_dir, new_dir) in &dirs {
+        let entries = match std::fs::read_dir(old_dir) {
+            Ok(x) => x,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", old_dir.display()));
+            }
+        };
+
+        for entry in entries {
+            let old_path = entry?.path();
+            let new_path = new_dir.join(old_path.file_name().unwrap());
+
+            if new_path.symlink_metadata().is_ok() {
+                log::warn!("not overwriting {}", new_path.display());
+                continue;
+            }
+
+            changes += 1;
+
+            if dry_run {
+                println!("{} -> {}", old_path.display(), new_path.display());
+                continue;
+            }
+
+            std::fs::create_dir_all(new_dir)
+                .with_context(|| format!("failed to create {}", new_dir.display()))?;
+
+            let is_shim = std::fs::read_link(&old_path)
+                .is_ok_and(|target| target.file_name() == Some(OsStr::new("quickenv")));
+
+            if is_shim {
+                symlink(&shim_target, &new_path).with_context(|| {
+                    format!(
+                        "failed to symlink {} to {}",
+                        shim_target.display(),
+                        new_path.display()
+                    )
+                })?;
+                std::fs::remove_file(&old_path)?;
+            } else {
+                move_file(&old_path, &new_path)?;
+            }
+        }
+
+        // only succeeds if we moved everything
+        let _ = std::fs::remove_dir(old_dir);
+    }
+
+    if dry_run {
+        return Ok(());
+    }
+
+    if std::fs::remove_dir(&legacy_home).is_err() {
+        log::warn!(
+            "{} still contains files that were not migrated",
+            style(legacy_home.display()).cyan()
+        );
+    }
+
+    log::info!(
+        "Migrated {} files to {}.",
+        style(changes).green(),
+        style(new_home.display()).cyan(),
+    );
+
+    if migrate_bin_dir {
+        log::info!(
+            "Update your PATH to contain {} instead of {}",
+            style(new_home.join("bin/").display()).cyan(),
+            style(legacy_home.join("bin/").display()).cyan(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Rename a file, falling back to copying it if it needs to move to another filesystem.
+fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    std::fs::rename(from, to)
+        .or_else(|_| std::fs::copy(from, to).and_then(|_| std::fs::remove_file(from)))
+        .with_context(|| format!("failed to move {} to {}", from.display(), to.display()))
+}
+
+/// A symlink in the bin directory pointing to a quickenv binary.
+struct Shim {
+    name: OsString,
+    /// The quickenv binary the symlink points to, which may no longer exist.
+    target: PathBuf,
+}
+
+/// List all shims in the bin directory, sorted by name.
+///
+/// Only symlinks pointing to a quickenv binary are considered, as the bin directory may be shared
+/// with other programs when QUICKENV_BIN_DIR is set.
+fn list_shims(bin_dir: &Path) -> Result<Vec<Shim>, Error> {
+    let mut rv = Vec::new();
+
+    let entries = match std::fs::read_dir(bin_dir) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(rv),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read {}", bin_dir.display()));
+        }
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        if let Ok(target) = std::fs::read_link(entry.path()) {
+            if target.file_name() == Some(OsStr::new("quickenv")) {
+                rv.push(Shim {
+                    name: entry.file_name(),
+                    // relative symlinks are relative to the directory they are in
+                    target: bin_dir.join(target),
+                });
+            }
+        }
+    }
+
+    rv.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(rv)
+}
+
+fn command_unshim(
+    commands: Vec<String>,
+    porcelain: bool,
+    prune_broken: bool,
+    also_clear_cache: bool,
+) -> Result<(), Error> {
+    let quickenv_dir = crate::core::get_quickenv_home()?;
+    let bin_dir = core::get_bin_dir(&quickenv_dir);
+
+    let mut expanded_commands = Vec::new();
+
+    if prune_broken {
+        let envvars = get_shim_envvars(&quickenv_dir)?;
+        for shim in list_shims(&bin_dir)? {
+            let Some(name) = shim.name.to_str() else {
+                continue;
+            };
+
+            if !shim.target.exists() {
+                log::debug!(
+                    target: LOG_SHIM,
+                    "{} points to missing {}",
+                    name,
+                    shim.target.display(),
+                );
+            } else if let Err(e) = find_in_shim_envvars(&quickenv_dir, &shim.name, &envvars) {
+                log::debug!(target: LOG_SHIM, "{} cannot be resolved: {:?}", name, e);
+            } else {
+                continue;
+            }
+
+            expanded_commands.push(name.to_owned());
+        }
+    }
+    let mut existing_shims = None;
+
+    for command in commands {
+        if !command.contains(['*', '?', '[']) {
+            expanded_commands.push(command);
+            continue;
+        }
+
+        let pattern = glob::Pattern::new(&command)
+            .with_context(|| format!("invalid pattern {:?}", command))?;
+
+        let existing_shims = match existing_shims {
+            Some(ref x) => x,
+            None => existing_shims.insert(list_shims(&bin_dir)?),
+        };
+
+        let len_before = expanded_commands.len();
+        expanded_commands.extend(
+            existing_shims
+                .iter()
+                .filter_map(|shim| shim.name.to_str())
+                .filter(|name| pattern.matches(name))
+                .map(str::to_owned),
+        );
+
+        if expanded_commands.len() == len_before {
+            return Err(anyhow::anyhow!("{:?} did not match any shims", command));
+        }
+    }
+
+    let mut changes = 0;
+    for command in &expanded_commands {
+        if command == "quickenv" {
+            log::warn!("not unshimming own binary");
+            if porcelain {
+                println!("skipped-self {}", command);
+            }
+            continue;
+        }
+
+        alias::remove_alias(&quickenv_dir, command)?;
+
+        let command_path = bin_dir.join(command);
+        match std::fs::remove_file(&command_path) {
+            Ok(()) => {
+                changes += 1;
+                if porcelain {
+                    println!("removed {}", command);
+                } else if prune_broken {
+                    log::info!("Removed broken shim {}", style(command).cyan());
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if porcelain {
+                    println!("not-found {}", command);
+                } else {
+                    log::warn!("no shim named {} found", style(command).cyan());
+                }
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to remove {}", command_path.display()));
+            }
+        }
+    }
+
+    if also_clear_cache {
+        let ctx = resolve_envrc_context(&quickenv_dir)?;
+        let _ = std::fs::remove_file(core::get_reload_failure_path(&ctx));
+        let _ = std::fs::remove_file(core::get_last_reload_path(&ctx));
+        match std::fs::remove_file(&ctx.env_cache_path) {
+            Ok(()) => {
+                if porcelain {
+                    println!("cleared-cache {}", ctx.envrc_path.display());
+                } else {
+                    log::info!(
+                        "Removed the cached variables of {}",
+                        style(ctx.envrc_path.display()).cyan()
+                    );
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::debug!(target: LOG_SHIM, "no cache at {}", ctx.env_cache_path.display());
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to remove {}", ctx.env_cache_path.display()));
+            }
+        }
+    }
+
+    if porcelain {
+        return Ok(());
+    }
+
+    log::info!(
+        "Removed {} shims from {}.\nUse {} to add them again",
+        style(changes).green(),
+        style(bin_dir.display()).cyan(),
+        style("'quickenv shim <command>'").magenta(),
+    );
+
+    Ok(())
+}
+
+/// One element of the output of 'quickenv list --json'.
+#[derive(serde::Serialize)]
+struct ShimListEntry {
+    /// The shim's name, base64-encoded if it is not valid UTF-8.
+    name: String,
+    name_base64: bool,
+    /// The quickenv binary the shim points to.
+    target: String,
+    /// Whether the quickenv binary the shim points to still exists.
+    valid: bool,
+    /// The program the shim would run in the current directory, if any.
+    resolved: Option<String>,
+}
+
+fn command_list(json: bool) -> Result<(), Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let shims = list_shims(&core::get_bin_dir(&quickenv_home))?;
+
+    if !json {
+        for shim in shims {
+            if shim.target.exists() {
+                println!("{}", shim.name.to_string_lossy());
+            } else {
+                println!(
+                    "{} {}",
+                    shim.name.to_string_lossy(),
+                    style(format!("(broken, {} is missing)", shim.target.display())).red()
+                );
+            }
+        }
+
+        return Ok(());
+    }
+
+    let mut entries = Vec::new();
+
+    for shim in shims {
+        let (name, name_base64) = match shim.name.to_str() {
+            Some(name) => (name.to_owned(), false),
+            None => (
+                base64::engine::general_purpose::STANDARD.encode(shim.name.as_bytes()),
+                true,
+            ),
+        };
+
+        let resolved = if name_base64 {
+            None
+        } else {
+            find_shimmed_binary(&quickenv_home, &shim.name, false)
+                .ok()
+                .map(|result| result.path.to_string_lossy().into_owned())
+        };
+
+        entries.push(ShimListEntry {
+            name,
+            name_base64,
+            target: shim.target.to_string_lossy().into_owned(),
+            valid: shim.target.exists(),
+            resolved,
+        });
+    }
+
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &entries)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+struct CacheStatsEntry {
+    path: String,
+    /// Seconds since the cache was last written.
+    age: u64,
+}
+
+#[derive(serde::Serialize)]
+struct CacheStats {
+    dir: String,
+    count: usize,
+    /// Combined size of all caches in bytes.
+    total_size: u64,
+    oldest: Option<CacheStatsEntry>,
+    newest: Option<CacheStatsEntry>,
+    /// The .envrc files the caches belong to. Caches written without a header are not included.
+    projects: Vec<String>,
+}
+
+fn command_complete(command: CompleteCommand) -> Result<(), Error> {
+    // Completions must not print errors while the user is typing, and a plugin can't do anything
+    // about them anyway. Any failure just means there is nothing to complete, and warnings are
+    // only shown when debugging.
+    if log::max_level() < log::LevelFilter::Debug {
+        log::set_max_level(log::LevelFilter::Off);
+    }
+    let names = match get_completions(command) {
+        Ok(names) => names,
+        Err(e) => {
+            log::debug!("nothing to complete: {e:#}");
+            return Ok(());
+        }
+    };
+
+    let mut stdout = io::stdout().lock();
+    for name in names {
+        stdout.write_all(name.as_bytes())?;
+        stdout.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+fn get_completions(command: CompleteCommand) -> Result<Vec<OsString>, Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+
+    match command {
+        CompleteCommand::ShimCandidates => {
+            let ctx = match resolve_envrc_context(&quickenv_home) {
+                Ok(ctx) => ctx,
+                Err(core::Error::NoEnvrc) => return Ok(Vec::new()),
+                Err(e) => return Err(e.into()),
+            };
+            let Some(envvars) = core::get_envvars(&ctx)? else {
+                return Ok(Vec::new());
+            };
+
+            Ok(filter_shim_candidates(&quickenv_home, &ctx, &envvars, &[])?
+                .into_iter()
+                .map(OsString::from)
+                .collect())
+        }
+        CompleteCommand::InstalledShims => Ok(list_shims(&core::get_bin_dir(&quickenv_home))?
+            .into_iter()
+            .map(|shim| shim.name)
+            .collect()),
+    }
+}
+
+fn command_cache_stats(json: bool) -> Result<(), Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let env_cache_dir = core::get_env_cache_dir(&quickenv_home);
+
+    let mut count = 0;
+    let mut total_size = 0;
+    let mut oldest: Option<(std::time::SystemTime, PathBuf)> = None;
+    let mut newest: Option<(std::time::SystemTime, PathBuf)> = None;
+    let mut projects = Vec::new();
+
+    let read_dir = match std::fs::read_dir(&env_cache_dir) {
+        Ok(x) => Some(x),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            return Err(Error::new(e).context(format!("failed to read {}", env_cache_dir.display())))
+        }
+    };
+
+    for entry in read_dir.into_iter().flatten() {
+        let entry = entry?;
+        let path = entry.path();
+        // skip markers such as .failed, which live next to the caches
+        if path.extension().is_some() {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        count += 1;
+        total_size += metadata.len();
+        let mtime = metadata.modified()?;
+        if oldest.as_ref().is_none_or(|(x, _)| mtime < *x) {
+            oldest = Some((mtime, path.clone()));
+        }
+        if newest.as_ref().is_none_or(|(x, _)| mtime > *x) {
+            newest = Some((mtime, path.clone()));
+        }
+
+        if let Some(envrc_path) = std::fs::read(&path)
+            .ok()
+            .and_then(|contents| core::parse_env_cache_header(&contents))
+        {
+            projects.push(envrc_path.to_string_lossy().into_owned());
+        }
+    }
+
+    let now = std::time::SystemTime::now();
+    let to_entry = |(mtime, path): (std::time::SystemTime, PathBuf)| CacheStatsEntry {
+        path: path.to_string_lossy().into_owned(),
+        age: now.duration_since(mtime).unwrap_or_default().as_secs(),
+    };
+
+    projects.sort();
+    let stats = CacheStats {
+        dir: env_cache_dir.to_string_lossy().into_owned(),
+        count,
+        total_size,
+        oldest: oldest.map(to_entry),
+        newest: newest.map(to_entry),
+        projects,
+    };
+
+    let mut stdout = io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &stats)?;
+        writeln!(stdout)?;
+    } else {
+        writeln!(stdout, "dir: {}", stats.dir)?;
+        writeln!(stdout, "count: {}", stats.count)?;
+        writeln!(stdout, "total_size: {}", stats.total_size)?;
+        for (label, entry) in [("oldest", &stats.oldest), ("newest", &stats.newest)] {
+            if let Some(entry) = entry {
+                writeln!(
+                    stdout,
+                    "{}: {} ({} ago)",
+                    label,
+                    entry.path,
+                    format_age(entry.age)
+                )?;
+            }
+        }
+        for project in &stats.projects {
+            writeln!(stdout, "project: {}", project)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Format a number of seconds in its largest sensible unit, for example '3d' or '5m'.
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// Parse an age in the format printed by format_age, a number followed by 's', 'm', 'h' or 'd'.
+fn parse_age(value: &str) -> Result<Duration, String> {
+    let unit_secs = match value.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        _ => return Err("expected a number followed by s, m, h or d, such as '30d'".to_owned()),
+    };
+    let number: u64 = value[..value.len() - 1]
+        .parse()
+        .map_err(|e| format!("invalid number: {e}"))?;
+    Ok(Duration::from_secs(number.saturating_mul(unit_secs)))
+}
+
+fn command_gc(max_age: Duration, dry_run: bool) -> Result<(), Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let env_cache_dir = core::get_env_cache_dir(&quickenv_home);
+
+    let read_dir = match std::fs::read_dir(&env_cache_dir) {
+        Ok(x) => Some(x),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            return Err(Error::new(e).context(format!("failed to read {}", env_cache_dir.display())))
+        }
+    };
+
+    let now = std::time::SystemTime::now();
+    let mut count = 0;
+    let mut freed_size = 0;
+
+    let get_age = |metadata: std::fs::Metadata| -> io::Result<Duration> {
+        Ok(now.duration_since(metadata.modified()?).unwrap_or_default())
+    };
+
+    let mut paths = read_dir
+        .into_iter()
+        .flatten()
+        .map(|entry| Ok(entry?.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.sort();
+    let mut expired_caches = BTreeSet::new();
+
+    for path in paths {
+        let metadata = std::fs::symlink_metadata(&path)?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let size = metadata.len();
+        let is_marker = path.extension().is_some();
+        let mut age = get_age(metadata)?;
+        // Reloads that don't change any variables leave the cache untouched, but still count as
+        // using it.
+        if !is_marker {
+            if let Ok(reloaded) = std::fs::metadata(path.with_extension("reloaded")) {
+                age = age.min(get_age(reloaded)?);
+            }
+        }
+        // Markers such as .failed are deleted together with their cache, which sorts before them.
+        // If there is none, because it was never written or deleted separately, they still
+        // expire on their own.
+        let with_cache = is_marker && expired_caches.contains(&path.with_extension(""));
+        if !with_cache && age <= max_age {
+            continue;
+        }
+
+        if !is_marker {
+            count += 1;
+            expired_caches.insert(path.clone());
+        }
+        freed_size += size;
+        if !with_cache {
+            println!("{} ({} old)", path.display(), format_age(age.as_secs()));
+        }
+        if dry_run {
+            continue;
+        }
+
+        std::fs::remove_file(&path)
+            .with_context(|| format!("failed to delete {}", path.display()))?;
+    }
+
+    // Entries for missing programs are only meaningful for a short while anyway.
+    let missing_dir = missing::get_missing_dir(&quickenv_home);
+    for entry in std::fs::read_dir(&missing_dir).into_iter().flatten() {
+        let path = entry?.path();
+        let metadata = std::fs::symlink_metadata(&path)?;
+        if !metadata.is_file() || get_age(metadata)? <= max_age {
+            continue;
+        }
+        log::debug!("deleting {}", path.display());
+        if !dry_run {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("failed to delete {}", path.display()))?;
         }
     }
 
-    if changes == 0 {
-        log::info!("created {} new shims.", style("no").red());
-    } else {
-        log::info!(
-            "Created {} new shims in {}.",
-            style(changes).green(),
-            style(bin_dir.display()).cyan(),
-        );
+    if dry_run {
         log::info!(
-            "Use {} to remove them again.",
-            style("'quickenv unshim <command>'").magenta(),
+            "Would delete {} caches, freeing {} bytes.",
+            style(count).green(),
+            style(freed_size).green()
         );
-    }
-
-    if auto {
+    } else {
         log::info!(
-            "Use {} to run additional commands with {} enabled.",
-            style("'quickenv shim <command>'").magenta(),
-            style(".envrc").cyan()
+            "Deleted {} caches, freeing {} bytes.",
+            style(count).green(),
+            style(freed_size).green()
         );
     }
 
     Ok(())
 }
 
-fn command_unshim(commands: Vec<String>) -> Result<(), Error> {
-    let quickenv_dir = crate::core::get_quickenv_home()?;
-    let bin_dir = quickenv_dir.join("bin/");
-    let mut changes = 0;
-    for command in &commands {
-        if command == "quickenv" {
-            log::warn!("not unshimming own binary");
-            continue;
-        }
+/// Per-invocation options for shims, read from QUICKENV_SHIM_OPTS and the older single-purpose
+/// environment variables.
+#[derive(Default, Debug)]
+struct ShimOpts {
+    /// exec() the program instead of spawning it as a subprocess.
+    exec: bool,
+    /// Do not load the .envrc at all.
+    no_env: bool,
+    /// Only apply PATH from the .envrc, and no other variables.
+    path_only: bool,
+    /// Enable debug logging.
+    debug: bool,
+    /// Tokens in QUICKENV_SHIM_OPTS that were not understood.
+    unknown: Vec<String>,
+}
 
-        let command_path = bin_dir.join(command);
-        if std::fs::remove_file(&command_path).is_ok() {
-            changes += 1;
+impl ShimOpts {
+    fn from_env() -> Self {
+        let mut rv = ShimOpts {
+            exec: std::env::var("QUICKENV_SHIM_EXEC").unwrap_or_default() == "1",
+            no_env: std::env::var("QUICKENV_NO_SHIM").unwrap_or_default() == "1",
+            path_only: std::env::var("QUICKENV_PATH_ONLY").unwrap_or_default() == "1",
+            ..Default::default()
+        };
+
+        let opts = std::env::var("QUICKENV_SHIM_OPTS").unwrap_or_default();
+        for token in opts.split([' ', ',']).filter(|x| !x.is_empty()) {
+            match token {
+                "exec" => rv.exec = true,
+                "no-env" => rv.no_env = true,
+                "path-only" => rv.path_only = true,
+                "debug" => rv.debug = true,
+                _ => rv.unknown.push(token.to_owned()),
+            }
         }
-    }
 
-    log::info!(
-        "Removed {} shims from {}.\nUse {} to add them again",
-        style(changes).green(),
-        style(bin_dir.display()).cyan(),
-        style("'quickenv shim <command>'").magenta(),
-    );
+        rv
+    }
+}
 
-    Ok(())
+/// Options for 'quickenv exec' that are not available for shims.
+#[derive(Default)]
+struct ExecOptions {
+    /// Working directory for the child process, if different from quickenv's own.
+    cwd: Option<PathBuf>,
+    /// Do not remove quickenv's bin directory from the PATH of the child process.
+    keep_shims: bool,
+    /// Fall back to looking up the program on quickenv's own PATH.
+    any: bool,
+    /// Log how long finding the program and running it took.
+    measure: bool,
+    /// Fail if there are no cached variables for the .envrc.
+    from_cache: bool,
+    /// Keep quickenv's bin directory on the PATH of the child process, without any safeguards.
+    no_path_strip: bool,
+    /// Only pass these variables from the .envrc to the child process.
+    vars: Option<Vec<String>>,
 }
 
-fn exec_shimmed_binary(program_name: &OsStr, args: Vec<OsString>) -> Result<(), Error> {
-    log::debug!("attempting to launch shim for {:?}", program_name);
+/// Variables quickenv sets for the child process itself, which are passed on even if
+/// 'exec --vars' does not list them.
+const INTERNAL_VARS: &[&str] = &["QUICKENV_NO_SHIM", "QUICKENV_RELOAD_MARKER", SHIM_DEPTH_VAR];
+
+/// Environment variable used to count how many shims are nested within each other, when
+/// '--keep-shims' allows them to call each other.
+const SHIM_DEPTH_VAR: &str = "QUICKENV_SHIM_DEPTH";
+const MAX_SHIM_DEPTH: u32 = 16;
+
+fn exec_shimmed_binary(
+    program_name: &OsStr,
+    args: Vec<OsString>,
+    options: ExecOptions,
+) -> Result<(), Error> {
+    log::debug!(target: LOG_SHIM, "attempting to launch shim for {:?}", program_name);
+
+    for token in ShimOpts::from_env().unknown {
+        log::warn!("ignoring unknown option {:?} in QUICKENV_SHIM_OPTS", token);
+    }
+
+    let depth = std::env::var(SHIM_DEPTH_VAR)
+        .ok()
+        .and_then(|x| x.parse::<u32>().ok())
+        .unwrap_or(0);
+    if depth >= MAX_SHIM_DEPTH {
+        anyhow::bail!(
+            "shims are nested {} levels deep, they are probably calling each other recursively",
+            depth
+        );
+    }
 
     let quickenv_home = crate::core::get_quickenv_home()?;
-    let shimmed_binary_result = find_shimmed_binary(&quickenv_home, program_name)
+    if is_raw_shim(&quickenv_home, program_name)? {
+        // also applies to nested shims, which see the environment without the .envrc as well
+        std::env::set_var("QUICKENV_NO_SHIM", "1");
+    }
+
+    let resolve_start = Instant::now();
+    let mut shimmed_binary_result = find_shimmed_binary(&quickenv_home, program_name, options.any)
         .context("failed to find actual binary")?;
+    if options.measure {
+        log::info!(
+            "measure: resolve {}",
+            format_millis(resolve_start.elapsed())
+        );
+    }
 
-    if std::env::var("QUICKENV_SHIM_EXEC").unwrap_or_default() == "1" {
+    if options.no_path_strip {
+        log::warn!(
+            "--no-path-strip is for debugging only: {} stays on PATH, and shims may recurse \
+             endlessly",
+            style(core::get_bin_dir(&quickenv_home).display()).cyan()
+        );
+        shimmed_binary_result
+            .envvars_override
+            .insert(OsString::from("PATH"), shimmed_binary_result.original_path);
+    } else if options.keep_shims {
+        shimmed_binary_result
+            .envvars_override
+            .insert(OsString::from("PATH"), shimmed_binary_result.original_path);
+        shimmed_binary_result.envvars_override.insert(
+            OsString::from(SHIM_DEPTH_VAR),
+            (depth + 1).to_string().into(),
+        );
+    }
+
+    if let Some(ref vars) = options.vars {
+        let exclude_path = vars.iter().any(|x| x == "-PATH");
+        shimmed_binary_result.envvars_override.retain(|key, _| {
+            let keep = if key == "PATH" {
+                !exclude_path
+            } else {
+                vars.iter().any(|x| key == x.as_str()) || INTERNAL_VARS.iter().any(|x| key == *x)
+            };
+            if !keep {
+                log::debug!(target: LOG_SHIM, "not passing {:?}, it is not in --vars", key);
+            }
+            keep
+        });
+    }
+
+    if ShimOpts::from_env().exec {
         for (k, v) in shimmed_binary_result.envvars_override {
-            log::debug!("export {:?}={:?}", k, v);
+            log::debug!(target: LOG_SHIM, "export {:?}={:?}", k, v);
             std::env::set_var(k, v);
         }
 
-        log::debug!("execvp {}", shimmed_binary_result.path.display());
+        if let Some(ref cwd) = options.cwd {
+            std::env::set_current_dir(cwd)
+                .with_context(|| format!("failed to change directory to {}", cwd.display()))?;
+        }
+
+        log::debug!(target: LOG_SHIM, "execvp {}", shimmed_binary_result.path.display());
 
         let mut full_args = vec![shimmed_binary_result.path.clone().into_os_string()];
         full_args.extend(args);
@@ -726,11 +3814,17 @@ fn exec_shimmed_binary(program_name: &OsStr, args: Vec<OsString>) -> Result<(),
             CheckUnshimmedCommands::new(&quickenv_home).unwrap_or(CheckUnshimmedCommands::Disabled);
         let _ignored = unshimmed_commands.exclude_current();
 
-        let exitcode = process::Command::new(shimmed_binary_result.path)
-            .args(args)
-            .envs(shimmed_binary_result.envvars_override)
-            .status()
-            .context("failed to spawn shim subcommand")?;
+        let mut cmd = process::Command::new(shimmed_binary_result.path);
+        cmd.args(args).envs(shimmed_binary_result.envvars_override);
+        if let Some(cwd) = options.cwd {
+            cmd.current_dir(cwd);
+        }
+
+        let run_start = Instant::now();
+        let exitcode = cmd.status().context("failed to spawn shim subcommand")?;
+        if options.measure {
+            log::info!("measure: run {}", format_millis(run_start.elapsed()));
+        }
 
         let _ignored = unshimmed_commands.check_unshimmed_commands(true);
 
@@ -738,27 +3832,129 @@ fn exec_shimmed_binary(program_name: &OsStr, args: Vec<OsString>) -> Result<(),
             std::process::exit(code);
         }
 
-        log::debug!("quickenv did not get an exitcode from child process, using exit 134");
+        log::debug!(
+            target: LOG_SHIM,
+            "quickenv did not get an exitcode from child process, using exit 134",
+        );
         std::process::exit(134)
     }
 }
 
+fn format_millis(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
 struct ShimmedBinaryResult {
     path: PathBuf,
     envvars_override: core::Env,
+    /// The PATH before quickenv's bin directory was removed from it.
+    original_path: OsString,
 }
 
+/// Find the program a shim would run, along with the environment to run it with.
+///
+/// With `any`, programs that are not on the .envrc's PATH are also looked up on the PATH quickenv
+/// itself was invoked with.
 fn find_shimmed_binary(
     quickenv_home: &Path,
     program_name: &OsStr,
+    any: bool,
 ) -> Result<ShimmedBinaryResult, Error> {
+    let mut envvars_override = get_cached_envvars(quickenv_home)?;
+    let original_path = envvars_override[OsStr::new("PATH")].clone();
+    strip_bin_dir_from_path(quickenv_home, &mut envvars_override);
+    let path = match find_in_shim_envvars_cached(quickenv_home, program_name, &envvars_override) {
+        Err(e) if any => {
+            log::debug!(
+                target: LOG_SHIM,
+                "{e:#}, falling back to the PATH quickenv was invoked with",
+            );
+            let mut ambient_envvars = core::Env::new();
+            ambient_envvars.insert(
+                OsString::from("PATH"),
+                std::env::var_os("PATH").unwrap_or_default(),
+            );
+            strip_bin_dir_from_path(quickenv_home, &mut ambient_envvars);
+            find_in_shim_envvars_cached(quickenv_home, program_name, &ambient_envvars)?
+        }
+        result => result?,
+    };
+
+    Ok(ShimmedBinaryResult {
+        path,
+        envvars_override,
+        original_path,
+    })
+}
+
+/// Determine the environment variables a shim runs its program with: The cached variables of the
+/// current .envrc, with quickenv's own bin directory removed from PATH.
+fn get_shim_envvars(quickenv_home: &Path) -> Result<core::Env, Error> {
+    let mut envvars_override = get_cached_envvars(quickenv_home)?;
+    strip_bin_dir_from_path(quickenv_home, &mut envvars_override);
+    Ok(envvars_override)
+}
+
+/// Printed to stderr by shims with QUICKENV_RELOAD_MARKER=1 when the cache of their .envrc is
+/// missing, older than the .envrc, or the last reload failed, followed by the path of the .envrc.
+const RELOAD_REQUIRED_MARKER: &str = "quickenv:reload-required";
+
+/// The cached variables of the current .envrc, with PATH always set.
+fn get_cached_envvars(quickenv_home: &Path) -> Result<core::Env, Error> {
     let mut envvars_override = BTreeMap::<OsString, OsString>::new();
 
-    if std::env::var("QUICKENV_NO_SHIM").unwrap_or_default() != "1" {
-        match resolve_envrc_context(quickenv_home).and_then(|ctx| core::get_envvars(&ctx)) {
+    let shim_opts = ShimOpts::from_env();
+    let mut reload_required_reported = false;
+    if !shim_opts.no_env {
+        match resolve_envrc_context(quickenv_home).and_then(|ctx| {
+            if std::env::var_os("QUICKENV_NO_SHIM").as_deref() == Some(ctx.envrc_path.as_os_str()) {
+                log::debug!(
+                    target: LOG_SHIM,
+                    "{} is already loaded by a parent shim",
+                    ctx.envrc_path.display(),
+                );
+                return Ok(None);
+            }
+
+            if let Some(pattern) = deny::find_denying_pattern(quickenv_home, &ctx.root)? {
+                log::debug!(
+                    target: LOG_SHIM,
+                    "not loading {}, it is denied by {:?}",
+                    ctx.envrc_path.display(),
+                    pattern,
+                );
+                return Ok(None);
+            }
+
+            let failure = core::get_reload_failure(&ctx);
+            if let Some(ref failure) = failure {
+                log::warn!(
+                    "the last {} of {} failed: {}",
+                    style("'quickenv reload'").magenta(),
+                    style(ctx.envrc_path.display()).cyan(),
+                    failure
+                );
+            }
+
+            let envvars = core::get_envvars(&ctx)?;
+            if std::env::var("QUICKENV_RELOAD_MARKER").as_deref() == Ok("1")
+                && (envvars.is_none() || failure.is_some() || !core::is_cache_fresh(&ctx))
+            {
+                eprintln!("{} {}", RELOAD_REQUIRED_MARKER, ctx.envrc_path.display());
+                reload_required_reported = true;
+            }
+
+            Ok(envvars.map(|envvars| (ctx.envrc_path, envvars)))
+        }) {
             Ok(None) => (),
-            Ok(Some(envvars)) => {
+            Ok(Some((envrc_path, mut envvars))) => {
+                if shim_opts.path_only {
+                    envvars.retain(|key, _| key == "PATH");
+                }
                 envvars_override.extend(envvars);
+                // Nested shims for the same .envrc should not load it a second time, as that would
+                // undo any changes the outer program made to the environment.
+                envvars_override.insert("QUICKENV_NO_SHIM".into(), envrc_path.into());
             }
             Err(core::Error::NoEnvrc) => (),
             Err(e) => {
@@ -767,18 +3963,32 @@ fn find_shimmed_binary(
         }
     }
 
-    let old_path = envvars_override
+    if reload_required_reported {
+        // only the outermost shim reports it, not every shim the program runs in turn
+        envvars_override.insert("QUICKENV_RELOAD_MARKER".into(), "0".into());
+    }
+
+    let path = envvars_override
         .get(OsStr::new("PATH"))
         .cloned()
         .or_else(|| std::env::var_os("PATH"))
         .ok_or_else(|| anyhow::anyhow!("failed to read PATH"))?;
+    envvars_override.insert(OsStr::new("PATH").to_owned(), path);
+    Ok(envvars_override)
+}
+
+/// Remove quickenv's own bin directory from PATH, such that shims don't end up launching
+/// themselves.
+fn strip_bin_dir_from_path(quickenv_home: &Path, envvars: &mut core::Env) {
+    let old_path = &envvars[OsStr::new("PATH")];
     let mut new_path = OsString::new();
 
-    for entry in std::env::split_paths(&old_path) {
-        if quickenv_home.join("bin") == entry
-            || std::fs::canonicalize(&entry).map_or(false, |x| x == quickenv_home.join("bin"))
-        {
-            log::debug!("removing own entry from PATH: {}", entry.display());
+    let bin_dir = core::get_bin_dir(quickenv_home);
+    let canonical_bin_dir = std::fs::canonicalize(&bin_dir).unwrap_or_else(|_| bin_dir.clone());
+
+    for entry in std::env::split_paths(old_path) {
+        if bin_dir == entry || std::fs::canonicalize(&entry).is_ok_and(|x| x == canonical_bin_dir) {
+            log::debug!(target: LOG_SHIM, "removing own entry from PATH: {}", entry.display());
             continue;
         }
 
@@ -789,25 +3999,150 @@ fn find_shimmed_binary(
         new_path.push(entry);
     }
 
-    envvars_override.insert(OsStr::new("PATH").to_owned(), new_path);
+    envvars.insert(OsStr::new("PATH").to_owned(), new_path);
+}
 
-    let program_basename = Path::new(&program_name)
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap();
+/// Determine the name of the program a shim runs, which is its own name unless it was created
+/// with 'quickenv shim --target'.
+fn resolve_alias(quickenv_home: &Path, program_name: &OsStr) -> Result<OsString, Error> {
+    let program_basename = Path::new(program_name).file_name().unwrap_or(program_name);
+    if let Some(name) = program_basename.to_str() {
+        if let Some(target) = alias::get_alias(quickenv_home, name)? {
+            log::debug!(target: LOG_SHIM, "{} is an alias for {}", name, target);
+            return Ok(target.into());
+        }
+
+        if let Some(target) = get_raw_shim_target(quickenv_home, name)? {
+            log::debug!(target: LOG_SHIM, "{} runs {} without .envrc", name, target);
+            return Ok(target.into());
+        }
+    }
+
+    Ok(program_basename.to_owned())
+}
+
+/// Shims named like a command plus this suffix run that command without loading the .envrc, as if
+/// QUICKENV_NO_SHIM=1 was set. Aliases created with 'quickenv shim --target' and executables that
+/// are actually called like that take precedence.
+const RAW_SHIM_SUFFIX: &str = "-raw";
+
+/// Whether a shim of the given name skips loading the .envrc, see `RAW_SHIM_SUFFIX`.
+fn is_raw_shim(quickenv_home: &Path, program_name: &OsStr) -> Result<bool, Error> {
+    let program_basename = Path::new(program_name).file_name().unwrap_or(program_name);
+    let Some(name) = program_basename.to_str() else {
+        return Ok(false);
+    };
 
-    let path = which::which_in(
+    Ok(get_raw_shim_target(quickenv_home, name)?.is_some())
+}
+
+/// The command a shim of the given name runs without loading the .envrc, if it is a raw shim.
+fn get_raw_shim_target<'a>(quickenv_home: &Path, name: &'a str) -> Result<Option<&'a str>, Error> {
+    let Some(target) = name.strip_suffix(RAW_SHIM_SUFFIX).filter(|x| !x.is_empty()) else {
+        return Ok(None);
+    };
+
+    if alias::get_alias(quickenv_home, name)?.is_some() || has_executable(quickenv_home, name)? {
+        return Ok(None);
+    }
+
+    Ok(Some(target))
+}
+
+/// Whether an executable of the given name, other than a shim, exists on the PATH of the current
+/// .envrc, or on the PATH quickenv was invoked with if there is none.
+///
+/// Unlike `get_shim_envvars`, this reads the cache without printing any warnings about it, as
+/// those are printed again when the shim loads it for real.
+fn has_executable(quickenv_home: &Path, name: &str) -> Result<bool, Error> {
+    let path_envvar = resolve_envrc_context(quickenv_home)
+        .ok()
+        .and_then(|ctx| core::get_envvars(&ctx).ok().flatten())
+        .and_then(|mut envvars| envvars.remove(OsStr::new("PATH")))
+        .or_else(|| std::env::var_os("PATH"))
+        .unwrap_or_default();
+    let mut envvars = core::Env::new();
+    envvars.insert(OsString::from("PATH"), path_envvar);
+    strip_bin_dir_from_path(quickenv_home, &mut envvars);
+
+    let self_binary = get_self_binary()?;
+    let cwd = std::env::current_dir().context("failed to get current working directory")?;
+    let found = which::which_in_all(name, envvars.get(OsStr::new("PATH")), cwd)
+        .is_ok_and(|mut candidates| candidates.any(|path| !is_self_binary(&path, &self_binary)));
+    Ok(found)
+}
+
+/// Like find_in_shim_envvars, but remembers failed lookups for QUICKENV_MISSING_CACHE_TTL, such
+/// that a shim of a program that is not available here fails fast when run repeatedly.
+fn find_in_shim_envvars_cached(
+    quickenv_home: &Path,
+    program_name: &OsStr,
+    envvars: &core::Env,
+) -> Result<PathBuf, Error> {
+    let ttl = get_missing_cache_ttl()?;
+    if ttl.is_zero() {
+        return find_in_shim_envvars(quickenv_home, program_name, envvars);
+    }
+
+    let cwd = std::env::current_dir().context("failed to get current working directory")?;
+    let path_envvar = envvars.get(OsStr::new("PATH")).map(OsString::as_os_str);
+    if let Some(age) =
+        missing::get_recently_missing(quickenv_home, program_name, path_envvar, &cwd, ttl)
+    {
+        log::debug!(
+            target: LOG_SHIM,
+            "not looking up {:?} again, it was missing {} ago",
+            program_name,
+            format_millis(age),
+        );
+        return Err(
+            anyhow::anyhow!("it was already missing {} ago", format_millis(age))
+                .context(format!("failed to find {}", program_name.to_string_lossy())),
+        );
+    }
+
+    let result = find_in_shim_envvars(quickenv_home, program_name, envvars);
+    if result.is_err() {
+        missing::set_missing(quickenv_home, program_name, path_envvar, &cwd, ttl);
+    }
+    result
+}
+
+/// Whether `path` is quickenv itself, such as a shim in a directory other than the bin directory.
+/// A shim would run quickenv again instead of the actual program there, so those are skipped.
+fn is_self_binary(path: &Path, self_binary: &Path) -> bool {
+    let is_self = std::fs::canonicalize(path).is_ok_and(|x| x == self_binary);
+    if is_self {
+        log::debug!(target: LOG_SHIM, "skipping {}, it points to quickenv", path.display());
+    }
+    is_self
+}
+
+/// Look up the program a shim of the given name would run, using the PATH from
+/// `get_shim_envvars`.
+fn find_in_shim_envvars(
+    quickenv_home: &Path,
+    program_name: &OsStr,
+    envvars: &core::Env,
+) -> Result<PathBuf, Error> {
+    let program_name = resolve_alias(quickenv_home, program_name)?;
+    let program_basename = program_name.to_str().unwrap();
+
+    // A directory on the .envrc's PATH may contain shims of its own. Those would run quickenv
+    // again instead of the actual program, so skip over them.
+    let self_binary = get_self_binary()?;
+
+    which::which_in_all(
         program_basename,
-        envvars_override.get(OsStr::new("PATH")),
+        envvars.get(OsStr::new("PATH")),
         std::env::current_dir().context("failed to get current working directory")?,
     )
-    .with_context(|| format!("failed to find {program_basename}"))?;
-
-    Ok(ShimmedBinaryResult {
-        path,
-        envvars_override,
+    .and_then(|mut candidates| {
+        candidates
+            .find(|path| !is_self_binary(path, &self_binary))
+            .ok_or(which::Error::CannotFindBinaryPath)
     })
+    .with_context(|| format!("failed to find {program_basename}"))
 }
 
 fn check_for_shim() -> Result<(), Error> {
@@ -822,31 +4157,291 @@ fn check_for_shim() -> Result<(), Error> {
         .to_str()
         .unwrap();
 
-    log::debug!("argv[0] is {:?}", program_name);
+    log::debug!(target: LOG_SHIM, "argv[0] is {:?}", program_name);
 
     if program_basename == "quickenv" {
-        log::debug!("own program name is quickenv, so no shim running");
+        log::debug!(target: LOG_SHIM, "own program name is quickenv, so no shim running");
         return Ok(());
     }
 
-    exec_shimmed_binary(&program_name, args_iter.collect())
+    if let Err(core::Error::NoQuickenvHome) = core::get_quickenv_home() {
+        // Shims may run in a cleared environment (cron, systemd units), where they should still
+        // behave like the command they stand in for.
+        return exec_unshimmed_binary(&program_name, args_iter.collect())
+            .with_context(|| format!("failed to run {}", program_basename));
+    }
+
+    exec_shimmed_binary(&program_name, args_iter.collect(), ExecOptions::default())
         .with_context(|| format!("failed to run {}", program_basename))
 }
 
-fn command_exec(program_name: OsString, args: Vec<OsString>) -> Result<(), Error> {
-    exec_shimmed_binary(&program_name, args)
+/// Run the program a shim stands in for without loading any .envrc, for when the bin directory
+/// cannot be determined.
+///
+/// Instead of the bin directory, all directories containing a symlink to quickenv under the
+/// program's name are removed from PATH.
+fn exec_unshimmed_binary(program_name: &OsStr, args: Vec<OsString>) -> Result<(), Error> {
+    log::debug!(
+        target: LOG_SHIM,
+        "cannot determine quickenv home, running {:?} without .envrc",
+        program_name,
+    );
+
+    let program_basename = Path::new(program_name).file_name().unwrap();
+    let self_binary = get_self_binary()?;
+    let old_path =
+        std::env::var_os("PATH").ok_or_else(|| anyhow::anyhow!("failed to read PATH"))?;
+
+    let mut new_path = Vec::new();
+    for entry in std::env::split_paths(&old_path) {
+        if std::fs::canonicalize(entry.join(program_basename)).is_ok_and(|x| x == self_binary) {
+            log::debug!(target: LOG_SHIM, "removing shim directory from PATH: {}", entry.display());
+            continue;
+        }
+
+        new_path.push(entry);
+    }
+
+    let new_path = std::env::join_paths(new_path).context("failed to build PATH")?;
+    let path = which::which_in(program_basename, Some(&new_path), std::env::current_dir()?)
+        .with_context(|| format!("failed to find {:?} on PATH", program_basename))?;
+
+    std::env::set_var("PATH", new_path);
+    log::debug!(target: LOG_SHIM, "execvp {}", path.display());
+
+    let mut full_args = vec![path.clone().into_os_string()];
+    full_args.extend(args);
+
+    Err(exec::execvp(&path, &full_args).into())
+}
+
+fn command_exec(
+    mut program_name: OsString,
+    mut args: Vec<OsString>,
+    git_root: bool,
+    shell: bool,
+    shell_functions: bool,
+    mut options: ExecOptions,
+) -> Result<(), Error> {
+    if shell {
+        let mut script = program_name;
+        for arg in args {
+            script.push(" ");
+            script.push(arg);
+        }
+
+        program_name = std::env::var_os("QUICKENV_SHELL")
+            .or_else(|| std::env::var_os("SHELL"))
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| "bash".into());
+        args = vec!["-lc".into(), script];
+    }
+
+    if let Some(cwd) = options.cwd.take() {
+        // resolve relative to where we were invoked from, before changing directories below
+        let cwd = std::env::current_dir()
+            .context("failed to get current directory")?
+            .join(cwd);
+        if !cwd.is_dir() {
+            return Err(anyhow::anyhow!("{} is not a directory", cwd.display()));
+        }
+        options.cwd = Some(cwd);
+    }
+
+    if git_root {
+        if options.cwd.is_none() {
+            // the program should still run in the directory it was invoked from
+            options.cwd = Some(std::env::current_dir().context("failed to get current directory")?);
+        }
+        change_to_git_root()?;
+    }
+
+    if shell_functions {
+        return exec_in_envrc(&program_name, args, options);
+    }
+
+    if options.from_cache {
+        let quickenv_home = crate::core::get_quickenv_home()?;
+        let ctx = resolve_envrc_context(&quickenv_home)?;
+        if core::get_envvars(&ctx)?.is_none() {
+            log::error!(
+                "No cached variables for {}, run {} first",
+                style(ctx.envrc_path.display()).cyan(),
+                style("'quickenv reload'").magenta()
+            );
+            std::process::exit(EXIT_NO_CACHE);
+        }
+    }
+
+    exec_shimmed_binary(&program_name, args, options)
+}
+
+/// Run the .envrc, and then the program within the same bash process, for 'quickenv exec
+/// --login-shell-functions'.
+fn exec_in_envrc(
+    program_name: &OsStr,
+    args: Vec<OsString>,
+    options: ExecOptions,
+) -> Result<(), Error> {
+    let quickenv_home = crate::core::get_quickenv_home()?;
+    let mut ctx = crate::core::resolve_envrc_context(&quickenv_home)?;
+
+    let mut envrc_contents = Vec::new();
+    ctx.envrc
+        .read_to_end(&mut envrc_contents)
+        .with_context(|| format!("failed to read {}", ctx.envrc_path.display()))?;
+    trust::ensure_trusted(&quickenv_home, &ctx, &envrc_contents)?;
+
+    let cwd = match options.cwd {
+        Some(cwd) => cwd,
+        None => std::env::current_dir().context("failed to get current directory")?,
+    };
+
+    let mut temp_script = tempfile::NamedTempFile::new_in(&ctx.root)
+        .with_context(|| format!("failed to create temporary file at {}", ctx.root.display()))?;
+    // The .envrc runs in its own directory and without the program's arguments, like during
+    // 'quickenv reload'. Its stdout is moved to stderr, to keep the program's stdout clean.
+    let mut script = format!(
+        r##"__quickenv_cwd=$1
+shift
+__quickenv_args=("$@")
+set --
+exec 3>&1 1>&2
+{prelude}
+"##,
+        prelude = get_prelude()?
+    )
+    .into_bytes();
+    script.extend_from_slice(&envrc_contents);
+    script.extend_from_slice(
+        br##"
+exec 1>&3 3>&-
+builtin cd -- "$__quickenv_cwd" || exit
+"${__quickenv_args[@]}"
+"##,
+    );
+    let temp_script_path = temp_script.path().to_owned();
+    temp_script.write_all(&script).with_context(|| {
+        format!(
+            "failed to write to temporary file at {}",
+            temp_script_path.display()
+        )
+    })?;
+
+    signals::pass_control_to_shim();
+
+    log::debug!(target: LOG_SHIM, "running {:?} after {}", program_name, ctx.envrc_path.display());
+
+    let exitcode = process::Command::new("bash")
+        .arg(&temp_script_path)
+        .arg(&cwd)
+        .arg(program_name)
+        .args(args)
+        // shims called by the program should not load the same .envrc on top of it again
+        .env("QUICKENV_NO_SHIM", &ctx.envrc_path)
+        .current_dir(&ctx.root)
+        .status()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::from(core::Error::NoShell),
+            _ => Error::from(e).context("failed to spawn bash for running envrc"),
+        })?;
+
+    drop(temp_script);
+
+    if let Some(code) = exitcode.code() {
+        std::process::exit(code);
+    }
+
+    log::debug!(
+        target: LOG_SHIM,
+        "quickenv did not get an exitcode from child process, using exit 134",
+    );
+    std::process::exit(134)
+}
+
+/// Output of 'quickenv which --json'.
+#[derive(serde::Serialize)]
+struct WhichOutput {
+    program: String,
+    winner: String,
+    candidates: Vec<String>,
 }
 
-fn command_which(program_name: OsString, pretend_shimmed: bool) -> Result<(), Error> {
+fn command_which(
+    program_names: Vec<OsString>,
+    pretend_shimmed: bool,
+    all: bool,
+    json: bool,
+) -> Result<(), Error> {
     let quickenv_home = crate::core::get_quickenv_home()?;
-    if !pretend_shimmed
-        && which::which(&program_name)? != quickenv_home.join("bin").join(&program_name)
-    {
-        log::error!("{:?} is not shimmed by quickenv", program_name);
-        std::process::exit(1);
+    let bin_dir = core::get_bin_dir(&quickenv_home);
+    // Whether a shim is installed makes no difference then, as it would only run the program
+    // found on the ambient PATH, which is what is looked up below.
+    let no_env = ShimOpts::from_env().no_env;
+    if no_env {
+        log::debug!(
+            target: LOG_SHIM,
+            "QUICKENV_NO_SHIM is set, resolving against the ambient PATH",
+        );
+    }
+
+    for program_name in &program_names {
+        if !pretend_shimmed && !no_env && which::which(program_name)? != bin_dir.join(program_name)
+        {
+            log::error!("{:?} is not shimmed by quickenv", program_name);
+            std::process::exit(1);
+        }
+    }
+
+    // shared between all programs, such that the .envrc cache is only read once
+    let envvars = get_shim_envvars(&quickenv_home)?;
+    let self_binary = get_self_binary()?;
+    let cwd = std::env::current_dir().context("failed to get current working directory")?;
+    let mut outputs = Vec::new();
+
+    for program_name in &program_names {
+        let winner = find_in_shim_envvars(&quickenv_home, program_name, &envvars)?;
+
+        let candidates = if all {
+            which::which_in_all(
+                resolve_alias(&quickenv_home, program_name)?,
+                envvars.get(OsStr::new("PATH")),
+                &cwd,
+            )?
+            // these would never run, just like for the winner
+            .filter(|path| !is_self_binary(path, &self_binary))
+            .collect()
+        } else {
+            vec![winner.clone()]
+        };
+
+        outputs.push(WhichOutput {
+            program: program_name.to_string_lossy().into_owned(),
+            winner: winner.to_string_lossy().into_owned(),
+            candidates: candidates
+                .iter()
+                .map(|x| x.to_string_lossy().into_owned())
+                .collect(),
+        });
+    }
+
+    if json {
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer_pretty(&mut stdout, &outputs)?;
+        writeln!(stdout)?;
+    } else {
+        // with several programs, each one may have any number of candidates
+        let label = all && outputs.len() > 1;
+        for output in outputs {
+            for candidate in output.candidates {
+                if label {
+                    println!("{}: {}", output.program, candidate);
+                } else {
+                    println!("{}", candidate);
+                }
+            }
+        }
     }
 
-    let shimmed_binary_result = find_shimmed_binary(&quickenv_home, &program_name)?;
-    println!("{}", shimmed_binary_result.path.display());
     Ok(())
 }
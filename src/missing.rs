@@ -0,0 +1,91 @@
+//! Short-lived cache of programs that shims failed to find.
+//!
+//! Tools like shell prompts tend to run the same shim over and over, even if the program it
+//! stands for does not exist in the current directory. Each of those lookups reads the cache of the
+//! .envrc and walks PATH, so with QUICKENV_MISSING_CACHE_TTL set, failures are remembered for that
+//! long. The entry is keyed by PATH, the working directory and the program name, such that a
+//! change to any of them invalidates it.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::core::get_env_cache_dir;
+
+/// Directory containing one empty file per program that was found to be missing.
+pub fn get_missing_dir(quickenv_home: &Path) -> PathBuf {
+    let cache_dir = get_env_cache_dir(quickenv_home);
+    cache_dir.parent().unwrap_or(&cache_dir).join("missing")
+}
+
+fn get_entry_path(
+    quickenv_home: &Path,
+    program_name: &OsStr,
+    path_envvar: Option<&OsStr>,
+    cwd: &Path,
+) -> PathBuf {
+    let mut hasher = blake3::Hasher::new();
+    for part in [
+        program_name,
+        path_envvar.unwrap_or_default(),
+        cwd.as_os_str(),
+    ] {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\0");
+    }
+    let key = hex::encode(hasher.finalize().as_bytes());
+    get_missing_dir(quickenv_home).join(key)
+}
+
+fn get_age(path: &Path) -> Option<Duration> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+}
+
+/// If the program was found to be missing less than `ttl` ago, return how long ago that was.
+pub fn get_recently_missing(
+    quickenv_home: &Path,
+    program_name: &OsStr,
+    path_envvar: Option<&OsStr>,
+    cwd: &Path,
+    ttl: Duration,
+) -> Option<Duration> {
+    let entry_path = get_entry_path(quickenv_home, program_name, path_envvar, cwd);
+    get_age(&entry_path).filter(|age| *age < ttl)
+}
+
+/// Remember that the program could not be found. Failures to do so are not fatal, the next lookup
+/// will just be as slow as this one.
+///
+/// Entries are never read again once they are older than `ttl`, so those are deleted here. Every
+/// working directory gets entries of its own, which would otherwise pile up.
+pub fn set_missing(
+    quickenv_home: &Path,
+    program_name: &OsStr,
+    path_envvar: Option<&OsStr>,
+    cwd: &Path,
+    ttl: Duration,
+) {
+    if let Ok(entries) = std::fs::read_dir(get_missing_dir(quickenv_home)) {
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if get_age(&path).is_none_or(|age| age >= ttl) {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    let entry_path = get_entry_path(quickenv_home, program_name, path_envvar, cwd);
+    let result = entry_path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::write(&entry_path, b""));
+    if let Err(e) = result {
+        log::debug!(
+            target: crate::LOG_SHIM,
+            "failed to remember that {program_name:?} is missing: {e}",
+        );
+    }
+}
@@ -1,9 +1,9 @@
-use anyhow::Error;
 use std::process::exit;
 
 use std::sync::atomic::{AtomicBool, Ordering};
 
 static SHIM_HAS_CONTROL: AtomicBool = AtomicBool::new(false);
+static PROMPT_ACTIVE: AtomicBool = AtomicBool::new(false);
 const INTERRUPTED_EXIT_CODE: i32 = 130;
 
 pub fn pass_control_to_shim() {
@@ -12,14 +12,75 @@ pub fn pass_control_to_shim() {
     SHIM_HAS_CONTROL.store(true, Ordering::SeqCst);
 }
 
-pub fn set_ctrlc_handler() -> Result<(), Error> {
-    ctrlc::set_handler(move || {
+/// Undo `pass_control_to_shim` once the subprocess has exited, such that Ctrl-C exits quickenv
+/// again.
+pub fn take_control_from_shim() {
+    SHIM_HAS_CONTROL.store(false, Ordering::SeqCst);
+}
+
+/// Marks an interactive prompt as active while it is alive. Create one around every prompt.
+///
+/// The terminal is restored when the guard is dropped, and by the Ctrl-C handler if quickenv is
+/// interrupted while the prompt is active, as destructors don't run then.
+pub struct PromptGuard {
+    _private: (),
+}
+
+impl PromptGuard {
+    pub fn new() -> Self {
+        PROMPT_ACTIVE.store(true, Ordering::SeqCst);
+        PromptGuard { _private: () }
+    }
+}
+
+impl Drop for PromptGuard {
+    fn drop(&mut self) {
+        PROMPT_ACTIVE.store(false, Ordering::SeqCst);
+        restore_terminal();
+    }
+}
+
+/// Show the cursor again, which prompts hide while they are active.
+fn restore_terminal() {
+    // necessary to work around https://github.com/mitsuhiko/dialoguer/issues/188
+    // prompts write to stderr, so that is where the cursor needs to be restored
+    let _ = console::Term::stderr().show_cursor();
+}
+
+/// Install the Ctrl-C handler. Returns whether that was successful.
+///
+/// This is best-effort: if a handler can't be registered (for example because one is already
+/// registered), quickenv keeps working, just without restoring the terminal on Ctrl-C.
+pub fn set_ctrlc_handler() -> bool {
+    let result = ctrlc::set_handler(move || {
         if !SHIM_HAS_CONTROL.load(Ordering::SeqCst) {
-            // necessary to work around https://github.com/mitsuhiko/dialoguer/issues/188
-            let term = console::Term::stdout();
-            term.show_cursor().unwrap();
+            if PROMPT_ACTIVE.load(Ordering::SeqCst) {
+                restore_terminal();
+            }
             exit(INTERRUPTED_EXIT_CODE);
         }
-    })?;
-    Ok(())
+    });
+
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            log::debug!("failed to set Ctrl-C handler, continuing without: {}", e);
+            false
+        }
+    }
+}
+
+#[test]
+fn test_set_ctrlc_handler_twice() {
+    set_ctrlc_handler();
+    assert!(!set_ctrlc_handler());
+}
+
+#[test]
+fn test_prompt_guard() {
+    {
+        let _guard = PromptGuard::new();
+        assert!(PROMPT_ACTIVE.load(Ordering::SeqCst));
+    }
+    assert!(!PROMPT_ACTIVE.load(Ordering::SeqCst));
 }
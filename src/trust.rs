@@ -0,0 +1,61 @@
+//! Record which .envrc files the user has reviewed and allowed to be executed.
+//!
+//! Similar to `direnv allow`, trust is tied to the exact contents of an .envrc: Every edit needs
+//! to be trusted again.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::core::{EnvrcContext, Error};
+
+pub fn get_trust_path(quickenv_home: &Path, ctx: &EnvrcContext) -> PathBuf {
+    get_trust_path_for_key(quickenv_home, &ctx.key)
+}
+
+pub fn get_trust_path_for_key(quickenv_home: &Path, key: &str) -> PathBuf {
+    quickenv_home.join("trusted").join(key)
+}
+
+fn hash_contents(envrc_contents: &[u8]) -> String {
+    hex::encode(blake3::hash(envrc_contents).as_bytes())
+}
+
+pub fn is_trusted(
+    quickenv_home: &Path,
+    ctx: &EnvrcContext,
+    envrc_contents: &[u8],
+) -> Result<bool, Error> {
+    match std::fs::read_to_string(get_trust_path(quickenv_home, ctx)) {
+        Ok(trusted_hash) => Ok(trusted_hash.trim() == hash_contents(envrc_contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn ensure_trusted(
+    quickenv_home: &Path,
+    ctx: &EnvrcContext,
+    envrc_contents: &[u8],
+) -> Result<(), Error> {
+    if is_trusted(quickenv_home, ctx, envrc_contents)? {
+        Ok(())
+    } else {
+        Err(Error::Untrusted(ctx.envrc_path.clone()))
+    }
+}
+
+pub fn trust(quickenv_home: &Path, ctx: &EnvrcContext, envrc_contents: &[u8]) -> Result<(), Error> {
+    let trust_path = get_trust_path(quickenv_home, ctx);
+    std::fs::create_dir_all(quickenv_home.join("trusted"))?;
+    std::fs::write(trust_path, hash_contents(envrc_contents))?;
+    Ok(())
+}
+
+/// Returns whether the .envrc was trusted before.
+pub fn untrust(quickenv_home: &Path, ctx: &EnvrcContext) -> Result<bool, Error> {
+    match std::fs::remove_file(get_trust_path(quickenv_home, ctx)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
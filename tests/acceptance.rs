@@ -1,4 +1,5 @@
 use std::fs::{create_dir_all, write};
+use std::path::Path;
 
 use anyhow::Error;
 use which::which;
@@ -10,6 +11,7 @@ use acceptance_helpers::{assert_cmd, set_executable, setup};
 fn test_basic() -> Result<(), Error> {
     let harness = setup()?;
     write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+    harness.trust()?;
     create_dir_all(harness.join("bogus"))?;
     write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
     set_executable(harness.join("bogus/hello"))?;
@@ -87,368 +89,456 @@ fn test_shadowed() -> Result<(), Error> {
 
     ----- stderr -----
     [ERROR quickenv] [scrubbed $HOME]/.quickenv/bin/hello is shadowed by an executable of the same name at [scrubbed $HOME]/project/bogus/hello
+    [ERROR quickenv] [scrubbed $HOME]/.quickenv/bin/ is entry 3 on your PATH, but [scrubbed $HOME]/project/bogus comes earlier as entry 1. Move [scrubbed $HOME]/.quickenv/bin/ before it, for example by adding 'export PATH="[scrubbed $HOME]/.quickenv/bin/:$PATH"' at the end of your shell's config file.
     "###);
     Ok(())
 }
 
 #[test]
-fn test_shadowing() -> Result<(), Error> {
-    let harness = setup()?;
-    assert_cmd!(harness, quickenv "shim" "true",  @r###"
+fn test_shadowed_no_shadow_check() -> Result<(), Error> {
+    let mut harness = setup()?;
+    harness.prepend_path(harness.join("bogus"));
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+    assert_cmd!(harness, quickenv "shim" "--no-shadow-check" "hello",  @r###"
     success: true
     exit_code: 0
     ----- stdout -----
 
     ----- stderr -----
+    [WARN quickenv] [scrubbed $HOME]/.quickenv/bin/hello is shadowed by an executable of the same name at [scrubbed $HOME]/project/bogus/hello
+    [WARN quickenv] [scrubbed $HOME]/.quickenv/bin/ is entry 3 on your PATH, but [scrubbed $HOME]/project/bogus comes earlier as entry 1. Move [scrubbed $HOME]/.quickenv/bin/ before it, for example by adding 'export PATH="[scrubbed $HOME]/.quickenv/bin/:$PATH"' at the end of your shell's config file.
     Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
     Use 'quickenv unshim <command>' to remove them again.
     "###);
+    assert!(harness.join("../.quickenv/bin/hello").exists());
     Ok(())
 }
 
 #[test]
-fn test_shim_self() -> Result<(), Error> {
-    let harness = setup()?;
-    assert_cmd!(harness, quickenv "unshim" "quickenv",  @r###"
+fn test_shim_porcelain() -> Result<(), Error> {
+    let mut harness = setup()?;
+    harness.prepend_path(harness.join("bogus"));
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+    write(
+        harness.join("bogus/goodbye"),
+        "#!/bin/sh\necho goodbye world",
+    )?;
+    set_executable(harness.join("bogus/goodbye"))?;
+
+    assert_cmd!(harness, quickenv "shim" "--porcelain" "true", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
+    created true
 
     ----- stderr -----
-    [WARN quickenv] not unshimming own binary
-    Removed 0 shims from [scrubbed $HOME]/.quickenv/bin/.
-    Use 'quickenv shim <command>' to add them again
     "###);
-    assert_cmd!(harness, quickenv "shim" "quickenv",  @r###"
+    assert_cmd!(harness, quickenv "shim" "--porcelain" "--no-shadow-check" "true" "quickenv" "hello" "false", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
+    unchanged true
+    skipped-self quickenv
+    created-shadowed hello
+    created false
 
     ----- stderr -----
     [WARN quickenv] not shimming own binary
-    created no new shims.
+    [WARN quickenv] [scrubbed $HOME]/.quickenv/bin/hello is shadowed by an executable of the same name at [scrubbed $HOME]/project/bogus/hello
+    [WARN quickenv] [scrubbed $HOME]/.quickenv/bin/ is entry 3 on your PATH, but [scrubbed $HOME]/project/bogus comes earlier as entry 1. Move [scrubbed $HOME]/.quickenv/bin/ before it, for example by adding 'export PATH="[scrubbed $HOME]/.quickenv/bin/:$PATH"' at the end of your shell's config file.
     "###);
-    assert_cmd!(harness, quickenv "unshim" "quickenv",  @r###"
+    assert_cmd!(harness, quickenv "unshim" "--porcelain" "true" "goodbye" "quickenv" "hello", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
+    removed true
+    not-found goodbye
+    skipped-self quickenv
+    removed hello
 
     ----- stderr -----
     [WARN quickenv] not unshimming own binary
-    Removed 0 shims from [scrubbed $HOME]/.quickenv/bin/.
-    Use 'quickenv shim <command>' to add them again
     "###);
-    Ok(())
-}
 
-#[test]
-fn test_verbosity() -> Result<(), Error> {
-    let mut harness = setup()?;
-    assert_cmd!(harness, quickenv "vars",  @r###"
+    // only a shim that does not exist is reported as not found
+    create_dir_all(harness.join("../.quickenv/bin/world"))?;
+    assert_cmd!(harness, quickenv "unshim" "--porcelain" "world", @r###"
     success: false
     exit_code: 1
     ----- stdout -----
 
     ----- stderr -----
-    [ERROR quickenv] failed to find .envrc in current or any parent directory
-    "###);
-    harness.set_var("QUICKENV_LOG", "debug");
-    assert_cmd!(harness, quickenv "vars",  @r###"
-    success: false
-    exit_code: 1
-    ----- stdout -----
+    [ERROR quickenv] failed to remove [scrubbed $HOME]/.quickenv/bin/world
 
-    ----- stderr -----
-    [DEBUG quickenv] argv[0] is "[scrubbed $HOME]/.quickenv/quickenv_bin/quickenv"
-    [DEBUG quickenv] own program name is quickenv, so no shim running
-    [ERROR quickenv] failed to find .envrc in current or any parent directory
+    Caused by:
+        Is a directory (os error 21)
     "###);
     Ok(())
 }
 
 #[test]
-fn test_script_failure() -> Result<(), Error> {
+fn test_unshim_missing() -> Result<(), Error> {
     let harness = setup()?;
-    write(harness.join(".envrc"), "exit 1")?;
-    assert_cmd!(harness, quickenv "reload",  @r###"
-    success: false
-    exit_code: 1
+
+    assert_cmd!(harness, quickenv "unshim" "helo", @r###"
+    success: true
+    exit_code: 0
     ----- stdout -----
 
     ----- stderr -----
-    [ERROR quickenv] .envrc exited with status exit status: 1
+    [WARN quickenv] no shim named helo found
+    Removed 0 shims from [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv shim <command>' to add them again
     "###);
     Ok(())
 }
 
 #[test]
-fn test_eating_own_tail() -> Result<(), Error> {
-    let harness = setup()?;
-    assert_cmd!(harness, quickenv "shim" "bash",  @r###"
+fn test_unshim_also_clear_cache() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=hello\n")?;
+    harness.trust()?;
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "1");
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
+    let output = harness.quickenv_output(&["shim", "hello", "goodbye"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    assert_cmd!(harness, quickenv "unshim" "hello", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
 
     ----- stderr -----
-    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
-    Use 'quickenv unshim <command>' to remove them again.
+    Removed 1 shims from [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv shim <command>' to add them again
     "###);
-    write(
-        harness.join(".envrc"),
-        "bash -c 'echo hello world'; export PATH=bogus:$PATH",
-    )?;
-    assert_cmd!(harness, quickenv "reload",  @r###"
+    assert_cmd!(harness, quickenv "vars", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
-    hello world
+    MYVALUE=hello
 
     ----- stderr -----
     "###);
-    create_dir_all(harness.join("bogus"))?;
-    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
-    set_executable(harness.join("bogus/hello"))?;
-    assert_cmd!(harness, quickenv "shim" "hello",  @r###"
+
+    assert_cmd!(harness, quickenv "unshim" "--also-clear-cache" "goodbye", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
 
     ----- stderr -----
-    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
-    Use 'quickenv unshim <command>' to remove them again.
+    Removed the cached variables of [scrubbed $HOME]/project/.envrc
+    Removed 1 shims from [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv shim <command>' to add them again
     "###);
-    assert_cmd!(harness, hello,  @r###"
-    success: true
-    exit_code: 0
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: false
+    exit_code: 4
     ----- stdout -----
-    hello world
 
     ----- stderr -----
+    [ERROR quickenv] Run 'quickenv reload' first to generate envvars
     "###);
     Ok(())
 }
 
 #[test]
-fn test_eating_own_tail2() -> Result<(), Error> {
-    let harness = setup()?;
-    assert_cmd!(harness, quickenv "shim" "bash",  @r###"
+fn test_reload_marker() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=hello\n")?;
+    harness.trust()?;
+    let output = harness.quickenv_output(&["shim", "echo"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    harness.set_var("QUICKENV_RELOAD_MARKER", "1");
+    assert_cmd!(harness, echo "hi", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
+    hi
 
     ----- stderr -----
-    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
-    Use 'quickenv unshim <command>' to remove them again.
+    quickenv:reload-required [scrubbed $HOME]/project/.envrc
     "###);
-    write(
-        harness.join(".envrc"),
-        "echo the value is $MYVALUE\nexport MYVALUE=canary",
-    )?;
-    assert_cmd!(harness, quickenv "reload",  @r###"
+
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "1");
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
+    assert_cmd!(harness, echo "hi", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
-    the value is
+    hi
 
     ----- stderr -----
     "###);
-    // assert that during reloading, we're not shimming bash and accidentally sourcing the old
-    // envvar values. canary should not appear during reload.
-    assert_cmd!(harness, quickenv "reload",  @r###"
+    Ok(())
+}
+
+#[test]
+fn test_shim_priority() -> Result<(), Error> {
+    let mut harness = setup()?;
+    create_dir_all(harness.join("../other"))?;
+    write(harness.join("../other/hello"), "#!/bin/sh\necho other")?;
+    set_executable(harness.join("../other/hello"))?;
+
+    // right after the bin directory, which is the second entry
+    let other = harness.join("../other");
+    let path = harness.var("PATH").unwrap().to_str().unwrap().to_owned();
+    let mut entries: Vec<&str> = path.split(':').collect();
+    entries.insert(2, other.to_str().unwrap());
+    harness.set_var("PATH", entries.join(":"));
+
+    assert_cmd!(harness, quickenv "shim" "--priority" "hello", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
-    the value is
 
     ----- stderr -----
+    hello is found in these entries of PATH:
+      2: [scrubbed $HOME]/.quickenv/bin (quickenv shim)
+      3: [scrubbed $HOME]/project/../other
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
     "###);
     Ok(())
 }
 
 #[test]
-fn test_exec() -> Result<(), Error> {
+fn test_complete() -> Result<(), Error> {
     let harness = setup()?;
+    let output = harness.quickenv_output(&["shim", "--no-verify", "hello", "goodbye"])?;
+    assert!(output.status.success(), "{:?}", output);
 
-    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
-    create_dir_all(harness.join("bogus"))?;
-    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
-    set_executable(harness.join("bogus/hello"))?;
-
-    assert_cmd!(harness, quickenv "reload", @r###"
+    assert_cmd!(harness, quickenv "__complete" "installed-shims", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
+    goodbye
+    hello
 
     ----- stderr -----
-    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
-    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
     "###);
-
-    harness.which("hello").unwrap_err();
-    assert_cmd!(harness, quickenv "exec" "hello", @r###"
+    // without an .envrc, there is nothing to recommend, and no error either
+    assert_cmd!(harness, quickenv "__complete" "shim-candidates", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
-    hello world
 
     ----- stderr -----
     "###);
-    Ok(())
-}
 
-#[test]
-fn test_shim_creating_shims() -> Result<(), Error> {
-    let harness = setup()?;
-
-    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
-    assert_cmd!(harness, quickenv "reload", @r###"
+    // neither without a cache, nor if anything else goes wrong
+    write(harness.join(".envrc"), "export PATH=$PWD/bogus:$PATH\n")?;
+    assert_cmd!(harness, quickenv "__complete" "shim-candidates", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
 
     ----- stderr -----
     "###);
-
-    create_dir_all(harness.join("bogus"))?;
-    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
-    set_executable(harness.join("bogus/hello"))?;
-
-    // there is a command. it does not create more commands. quickenv should not amend any output
-    assert_cmd!(harness, quickenv "exec" "hello", @r###"
+    write(harness.join(".quickenv.toml"), "shim_exclude = 1\n")?;
+    assert_cmd!(harness, quickenv "__complete" "shim-candidates", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
-    hello world
 
     ----- stderr -----
     "###);
+    Ok(())
+}
 
-    // shimming the command should work
-    assert_cmd!(harness, quickenv "shim" "--yes", @r###"
-    success: true
-    exit_code: 0
-    ----- stdout -----
+#[test]
+fn test_log_targets() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=hello\n")?;
+    harness.trust()?;
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "1");
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
 
-    ----- stderr -----
-    Found these unshimmed commands in your .envrc:
+    harness.set_var("QUICKENV_LOG", "quickenv::core=debug");
+    let output = harness.quickenv_output(&["exec", "true"])?;
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("loading "), "{}", stderr);
+    assert!(!stderr.contains("attempting to launch shim"), "{}", stderr);
 
-    hello
+    harness.set_var("QUICKENV_LOG", "quickenv::shim=debug");
+    let output = harness.quickenv_output(&["exec", "true"])?;
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(!stderr.contains("loading "), "{}", stderr);
+    assert!(stderr.contains("attempting to launch shim"), "{}", stderr);
+    Ok(())
+}
 
-    Quickenv will create this new shim binary in [scrubbed $HOME]/.quickenv/bin/.
-    Inside of [scrubbed $HOME]/project, those commands will run with .envrc enabled.
-    Outside, they will run normally.
-    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
-    Use 'quickenv unshim <command>' to remove them again.
-    Use 'quickenv shim <command>' to run additional commands with .envrc enabled.
-    "###);
+#[test]
+fn test_missing_cache() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=$PWD/tools:$PATH\n")?;
+    harness.trust()?;
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "1");
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
 
-    // change the command such that it creates another command, and run it
-    write(
-        harness.join("bogus/hello"),
-        "#!/bin/sh\necho 'echo hello world' > bogus/hello2 && chmod +x bogus/hello2",
-    )?;
-    set_executable(harness.join("bogus/hello"))?;
+    harness.set_var("QUICKENV_LOG", "quickenv::shim=debug");
+    harness.set_var("QUICKENV_MISSING_CACHE_TTL", "1");
+    let output = harness.quickenv_output(&["exec", "greet"])?;
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("failed to find greet"), "{}", stderr);
+    assert!(!stderr.contains("not looking up"), "{}", stderr);
 
-    // quickenv should warn that more commands need shimming now
-    assert_cmd!(harness, quickenv "exec" "hello", @r###"
+    let output = harness.quickenv_output(&["exec", "greet"])?;
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("failed to find greet"), "{}", stderr);
+    assert!(
+        stderr.contains("not looking up \"greet\" again"),
+        "{}",
+        stderr
+    );
+
+    // once the entry expired, the program is found
+    create_dir_all(harness.join("tools"))?;
+    write(harness.join("tools/greet"), "#!/bin/sh\necho hello")?;
+    set_executable(harness.join("tools/greet"))?;
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    harness.set_var("QUICKENV_LOG", "error");
+    assert_cmd!(harness, quickenv "exec" "greet", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
+    hello
 
     ----- stderr -----
-    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
-    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
     "###);
 
-    // quickenv shim should find the new command
-    assert_cmd!(harness, quickenv "shim" "--yes", @r###"
-    success: true
-    exit_code: 0
+    // the next failed lookup cleans up the expired entry of greet
+    let output = harness.quickenv_output(&["exec", "bogus"])?;
+    assert!(!output.status.success(), "{:?}", output);
+    assert_eq!(
+        std::fs::read_dir(harness.join("../.quickenv/missing"))?.count(),
+        1
+    );
+
+    // without QUICKENV_MISSING_CACHE_TTL, every lookup is done again
+    harness
+        .env
+        .remove(std::ffi::OsStr::new("QUICKENV_MISSING_CACHE_TTL"));
+    harness.set_var("QUICKENV_LOG", "quickenv::shim=debug");
+    for _ in 0..2 {
+        let output = harness.quickenv_output(&["exec", "bogus"])?;
+        let stderr = String::from_utf8(output.stderr)?;
+        assert!(!stderr.contains("not looking up"), "{}", stderr);
+    }
+
+    // too large to be represented as a duration
+    harness.set_var("QUICKENV_MISSING_CACHE_TTL", "1e20");
+    harness.set_var("QUICKENV_LOG", "error");
+    assert_cmd!(harness, quickenv "exec" "greet", @r###"
+    success: false
+    exit_code: 1
     ----- stdout -----
 
     ----- stderr -----
-    Found these unshimmed commands in your .envrc:
-
-    hello2
+    [ERROR quickenv] failed to find actual binary
 
-    Quickenv will create this new shim binary in [scrubbed $HOME]/.quickenv/bin/.
-    Inside of [scrubbed $HOME]/project, those commands will run with .envrc enabled.
-    Outside, they will run normally.
-    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
-    Use 'quickenv unshim <command>' to remove them again.
-    Use 'quickenv shim <command>' to run additional commands with .envrc enabled.
+    Caused by:
+        invalid QUICKENV_MISSING_CACHE_TTL "1e20", expected seconds
     "###);
-
     Ok(())
 }
 
 #[test]
-fn test_auto_shimming() -> Result<(), Error> {
-    let harness = setup()?;
-
-    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+fn test_shim_without_home() -> Result<(), Error> {
+    let mut harness = setup()?;
     create_dir_all(harness.join("bogus"))?;
     write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
     set_executable(harness.join("bogus/hello"))?;
+    let mut path = harness.var("PATH").unwrap().to_owned();
+    path.push(":");
+    path.push(harness.join("bogus"));
+    harness.set_var("PATH", &path);
 
-    assert_cmd!(harness, quickenv "reload", @r###"
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
 
     ----- stderr -----
-    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
-    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
     "###);
 
-    assert_cmd!(harness, quickenv "shim" "-y", @r###"
+    let _guard = harness.insta_settings().bind_to_scope();
+    insta_cmd::assert_cmd_snapshot!(
+        std::process::Command::new(harness.which("hello")?)
+            .current_dir(&harness.cwd)
+            .env_clear()
+            .env("PATH", &path),
+        @r###"
     success: true
     exit_code: 0
     ----- stdout -----
+    hello world
 
     ----- stderr -----
-    Found these unshimmed commands in your .envrc:
+    "###
+    );
+    Ok(())
+}
 
-    hello
+#[test]
+fn test_shim_recreate() -> Result<(), Error> {
+    let harness = setup()?;
+    assert_cmd!(harness, quickenv "shim" "true" "false" "echo", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
 
-    Quickenv will create this new shim binary in [scrubbed $HOME]/.quickenv/bin/.
-    Inside of [scrubbed $HOME]/project, those commands will run with .envrc enabled.
-    Outside, they will run normally.
-    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    ----- stderr -----
+    Created 3 new shims in [scrubbed $HOME]/.quickenv/bin/.
     Use 'quickenv unshim <command>' to remove them again.
-    Use 'quickenv shim <command>' to run additional commands with .envrc enabled.
     "###);
 
-    assert_cmd!(harness, quickenv "shim" "-y", @r###"
+    for name in ["true", "false"] {
+        let path = harness.join("../.quickenv/bin").join(name);
+        std::fs::remove_file(&path)?;
+        std::os::unix::fs::symlink("../old/quickenv", &path)?;
+    }
+
+    assert_cmd!(harness, quickenv "shim" "--recreate", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
 
     ----- stderr -----
-    created no new shims.
-    Use 'quickenv shim <command>' to run additional commands with .envrc enabled.
+    Refreshed 2 shims in [scrubbed $HOME]/.quickenv/bin/, 1 were already up to date.
     "###);
-
-    Ok(())
-}
-
-#[test]
-fn test_no_envrc_context() -> Result<(), Error> {
-    let harness = setup()?;
-    assert_cmd!(harness, quickenv "shim" "echo", @r###"
+    assert_cmd!(harness, quickenv "list", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
+    echo
+    false
+    true
 
     ----- stderr -----
-    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
-    Use 'quickenv unshim <command>' to remove them again.
     "###);
-    assert_cmd!(harness, echo "hello world", @r###"
+    assert_cmd!(harness, quickenv "shim" "--recreate" "--porcelain", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
-    hello world
+    unchanged echo
+    unchanged false
+    unchanged true
 
     ----- stderr -----
     "###);
@@ -456,80 +546,175 @@ fn test_no_envrc_context() -> Result<(), Error> {
 }
 
 #[test]
-fn test_eating_own_tail3() -> Result<(), Error> {
-    // regression: we removed our own PATH from the PATH envvar, but:
-    // 1) the path is actually duplicated, so we didn't remove all copies, and we recurse into
-    //    the shim
-    // 2) the shim re-adds the PATH through its envrc cache
-    let mut harness = setup()?;
-    harness.prepend_path(std::fs::canonicalize(harness.join("../.quickenv/bin")).unwrap());
-    write(harness.join(".envrc"), "export PATH=hello:$PATH:")?;
-    assert_cmd!(harness, quickenv "reload", @r###"
+fn test_shadowing() -> Result<(), Error> {
+    let harness = setup()?;
+    assert_cmd!(harness, quickenv "shim" "true",  @r###"
     success: true
     exit_code: 0
     ----- stdout -----
 
     ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
     "###);
-    assert_cmd!(harness, quickenv "shim" "hello", @r###"
-    success: true
+    Ok(())
+}
+
+#[test]
+fn test_shim_self() -> Result<(), Error> {
+    let harness = setup()?;
+    assert_cmd!(harness, quickenv "unshim" "quickenv",  @r###"
+    success: true
     exit_code: 0
     ----- stdout -----
 
     ----- stderr -----
-    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
-    Use 'quickenv unshim <command>' to remove them again.
+    [WARN quickenv] not unshimming own binary
+    Removed 0 shims from [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv shim <command>' to add them again
+    "###);
+    assert_cmd!(harness, quickenv "shim" "quickenv",  @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] not shimming own binary
+    created no new shims.
+    "###);
+    assert_cmd!(harness, quickenv "unshim" "quickenv",  @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] not unshimming own binary
+    Removed 0 shims from [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv shim <command>' to add them again
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_verbosity() -> Result<(), Error> {
+    let mut harness = setup()?;
+    assert_cmd!(harness, quickenv "vars",  @r###"
+    success: false
+    exit_code: 3
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to find .envrc in current or any parent directory
     "###);
     harness.set_var("QUICKENV_LOG", "debug");
-    assert_cmd!(harness, hello, @r###"
+    assert_cmd!(harness, quickenv "vars",  @r###"
+    success: false
+    exit_code: 3
+    ----- stdout -----
+
+    ----- stderr -----
+    [DEBUG quickenv] argv[0] is "[scrubbed $HOME]/.quickenv/quickenv_bin/quickenv"
+    [DEBUG quickenv] own program name is quickenv, so no shim running
+    [ERROR quickenv] failed to find .envrc in current or any parent directory
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_script_failure() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "exit 1")?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload",  @r###"
+    success: false
+    exit_code: 5
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] .envrc exited with status exit status: 1
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_script_exits_early() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export A=1\nexit 0\n")?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
     success: false
     exit_code: 1
     ----- stdout -----
 
     ----- stderr -----
-    [DEBUG quickenv] argv[0] is "[scrubbed $HOME]/.quickenv/bin/hello"
-    [DEBUG quickenv] attempting to launch shim for "[scrubbed $HOME]/.quickenv/bin/hello"
-    [DEBUG quickenv] loading [scrubbed $HOME]/project/.envrc
-    [DEBUG quickenv] removing own entry from PATH: [scrubbed $HOME]/.quickenv/bin
-    [DEBUG quickenv] removing own entry from PATH: [scrubbed $HOME]/.quickenv/bin
-    [ERROR quickenv] failed to run shimmed command
+    [ERROR quickenv] failed to parse envrc output
 
     Caused by:
-        0: failed to run hello
-        1: failed to find actual binary
-        2: failed to find hello
-        3: cannot find binary path
+        .envrc exited before its environment could be read, did it call exit?
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: false
+    exit_code: 4
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] Run 'quickenv reload' first to generate envvars
     "###);
     Ok(())
 }
 
 #[test]
-fn test_which() -> Result<(), Error> {
+fn test_eating_own_tail() -> Result<(), Error> {
     let harness = setup()?;
+    assert_cmd!(harness, quickenv "shim" "bash",  @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
 
-    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    write(
+        harness.join(".envrc"),
+        "bash -c 'echo hello world'; export PATH=bogus:$PATH",
+    )?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload",  @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello world
+
+    ----- stderr -----
+    "###);
     create_dir_all(harness.join("bogus"))?;
     write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
     set_executable(harness.join("bogus/hello"))?;
-
-    assert_cmd!(harness, quickenv "reload", @r###"
+    assert_cmd!(harness, quickenv "shim" "hello",  @r###"
     success: true
     exit_code: 0
     ----- stdout -----
 
     ----- stderr -----
-    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
-    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
     "###);
-    assert_cmd!(harness, quickenv "which" "hello", @r###"
-    success: false
-    exit_code: 1
+    assert_cmd!(harness, hello,  @r###"
+    success: true
+    exit_code: 0
     ----- stdout -----
+    hello world
 
     ----- stderr -----
-    [ERROR quickenv] cannot find binary path
     "###);
-    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    Ok(())
+}
+
+#[test]
+fn test_eating_own_tail2() -> Result<(), Error> {
+    let harness = setup()?;
+    assert_cmd!(harness, quickenv "shim" "bash",  @r###"
     success: true
     exit_code: 0
     ----- stdout -----
@@ -538,11 +723,26 @@ fn test_which() -> Result<(), Error> {
     Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
     Use 'quickenv unshim <command>' to remove them again.
     "###);
-    assert_cmd!(harness, quickenv "which" "hello", @r###"
+    write(
+        harness.join(".envrc"),
+        "echo the value is $MYVALUE\nexport MYVALUE=canary",
+    )?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload",  @r###"
     success: true
     exit_code: 0
     ----- stdout -----
-    bogus/hello
+    the value is
+
+    ----- stderr -----
+    "###);
+    // assert that during reloading, we're not shimming bash and accidentally sourcing the old
+    // envvar values. canary should not appear during reload.
+    assert_cmd!(harness, quickenv "reload",  @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    the value is
 
     ----- stderr -----
     "###);
@@ -550,20 +750,49 @@ fn test_which() -> Result<(), Error> {
 }
 
 #[test]
-fn test_which_pretend_shimmed() -> Result<(), Error> {
+fn test_exec() -> Result<(), Error> {
     let harness = setup()?;
 
     write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+
+    harness.trust()?;
     create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
 
-    assert_cmd!(harness, quickenv "which" "bash", @r###"
-    success: false
-    exit_code: 1
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
     ----- stdout -----
 
     ----- stderr -----
-    [ERROR quickenv] "bash" is not shimmed by quickenv
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+
+    harness.which("hello").unwrap_err();
+    assert_cmd!(harness, quickenv "exec" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello world
+
+    ----- stderr -----
     "###);
+    Ok(())
+}
+
+#[test]
+fn test_exec_keep_shims() -> Result<(), Error> {
+    let mut harness = setup()?;
+
+    // append to PATH, such that the shim takes precedence over the actual binary
+    write(harness.join(".envrc"), "export PATH=$PATH:$PWD/bogus\n")?;
+
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
 
     assert_cmd!(harness, quickenv "reload", @r###"
     success: true
@@ -571,34 +800,4315 @@ fn test_which_pretend_shimmed() -> Result<(), Error> {
     ----- stdout -----
 
     ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
     "###);
 
-    assert_cmd!(harness, quickenv "which" "bash", @r###"
-    success: false
-    exit_code: 1
+    assert_cmd!(harness, quickenv "exec" "--" "sh" "-c" "command -v hello", @r###"
+    success: true
+    exit_code: 0
     ----- stdout -----
+    [scrubbed $HOME]/project/bogus/hello
 
     ----- stderr -----
-    [ERROR quickenv] "bash" is not shimmed by quickenv
     "###);
+    assert_cmd!(harness, quickenv "exec" "--keep-shims" "--" "sh" "-c" "command -v hello; hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [scrubbed $HOME]/.quickenv/bin/hello
+    hello world
 
-    write(harness.join("bogus/bash"), "#!/bin/sh\necho hello world")?;
-    set_executable(harness.join("bogus/bash"))?;
+    ----- stderr -----
+    "###);
 
-    assert_cmd!(harness, quickenv "which" "bash", @r###"
+    harness.set_var("QUICKENV_SHIM_DEPTH", "16");
+    assert_cmd!(harness, quickenv "exec" "--keep-shims" "hello", @r###"
     success: false
     exit_code: 1
     ----- stdout -----
 
     ----- stderr -----
-    [ERROR quickenv] "bash" is not shimmed by quickenv
+    [ERROR quickenv] shims are nested 16 levels deep, they are probably calling each other recursively
     "###);
+    Ok(())
+}
 
-    assert_cmd!(harness, quickenv "which" "bash" "--pretend-shimmed", @r###"
+#[test]
+fn test_nested_shims() -> Result<(), Error> {
+    let harness = setup()?;
+
+    write(
+        harness.join(".envrc"),
+        "export PATH=$PWD/bogus:$PATH\nexport MYVALUE=cached\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    // the shim of bash is invoked explicitly, as shims remove themselves from PATH
+    write(
+        harness.join("bogus/hello"),
+        "#!/bin/sh\nexport MYVALUE=changed\nexec $HOME/.quickenv/bin/bash -c 'echo $MYVALUE'",
+    )?;
+    set_executable(harness.join("bogus/hello"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
     success: true
     exit_code: 0
     ----- stdout -----
-    bogus/bash
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello" "bash", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 2 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    changed
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_shim_grid_columns() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=$PWD/bogus:$PATH\n")?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    for name in ["aaa", "bbb", "ccc", "ddd"] {
+        write(harness.join("bogus").join(name), "#!/bin/sh\n")?;
+        set_executable(harness.join("bogus").join(name))?;
+    }
+
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "1");
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+
+    // stderr is not a terminal here, so its size can only come from COLUMNS
+    harness.set_var("COLUMNS", "12");
+    assert_cmd!(harness, quickenv "shim" "--yes", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Found these unshimmed commands in your .envrc:
+
+    aaa  bbb
+    ccc  ddd
+
+    Quickenv will create these 4 new shim binaries in [scrubbed $HOME]/.quickenv/bin/.
+    Inside of [scrubbed $HOME]/project, those commands will run with .envrc enabled.
+    Outside, they will run normally.
+    Created 4 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    Use 'quickenv shim <command>' to run additional commands with .envrc enabled.
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_shim_creating_shims() -> Result<(), Error> {
+    let harness = setup()?;
+
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+
+    // there is a command. it does not create more commands. quickenv should not amend any output
+    assert_cmd!(harness, quickenv "exec" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello world
+
+    ----- stderr -----
+    "###);
+
+    // shimming the command should work
+    assert_cmd!(harness, quickenv "shim" "--yes", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Found these unshimmed commands in your .envrc:
+
+    hello
+
+    Quickenv will create this new shim binary in [scrubbed $HOME]/.quickenv/bin/.
+    Inside of [scrubbed $HOME]/project, those commands will run with .envrc enabled.
+    Outside, they will run normally.
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    Use 'quickenv shim <command>' to run additional commands with .envrc enabled.
+    "###);
+
+    // change the command such that it creates another command, and run it
+    write(
+        harness.join("bogus/hello"),
+        "#!/bin/sh\necho 'echo hello world' > bogus/hello2 && chmod +x bogus/hello2",
+    )?;
+    set_executable(harness.join("bogus/hello"))?;
+
+    // quickenv should warn that more commands need shimming now
+    assert_cmd!(harness, quickenv "exec" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+
+    // quickenv shim should find the new command
+    assert_cmd!(harness, quickenv "shim" "--yes", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Found these unshimmed commands in your .envrc:
+
+    hello2
+
+    Quickenv will create this new shim binary in [scrubbed $HOME]/.quickenv/bin/.
+    Inside of [scrubbed $HOME]/project, those commands will run with .envrc enabled.
+    Outside, they will run normally.
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    Use 'quickenv shim <command>' to run additional commands with .envrc enabled.
+    "###);
+
+    Ok(())
+}
+
+#[test]
+fn test_auto_shimming() -> Result<(), Error> {
+    let harness = setup()?;
+
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+
+    assert_cmd!(harness, quickenv "shim" "-y", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Found these unshimmed commands in your .envrc:
+
+    hello
+
+    Quickenv will create this new shim binary in [scrubbed $HOME]/.quickenv/bin/.
+    Inside of [scrubbed $HOME]/project, those commands will run with .envrc enabled.
+    Outside, they will run normally.
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    Use 'quickenv shim <command>' to run additional commands with .envrc enabled.
+    "###);
+
+    assert_cmd!(harness, quickenv "shim" "-y", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    created no new shims.
+    Use 'quickenv shim <command>' to run additional commands with .envrc enabled.
+    "###);
+
+    Ok(())
+}
+
+#[test]
+fn test_no_envrc_context() -> Result<(), Error> {
+    let harness = setup()?;
+    assert_cmd!(harness, quickenv "shim" "echo", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert_cmd!(harness, echo "hello world", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello world
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_eating_own_tail3() -> Result<(), Error> {
+    // regression: we removed our own PATH from the PATH envvar, but:
+    // 1) the path is actually duplicated, so we didn't remove all copies, and we recurse into
+    //    the shim
+    // 2) the shim re-adds the PATH through its envrc cache
+    let mut harness = setup()?;
+    harness.prepend_path(std::fs::canonicalize(harness.join("../.quickenv/bin")).unwrap());
+    write(harness.join(".envrc"), "export PATH=hello:$PATH:")?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    harness.set_var("QUICKENV_LOG", "debug");
+    assert_cmd!(harness, hello, @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [DEBUG quickenv] argv[0] is "[scrubbed $HOME]/.quickenv/bin/hello"
+    [DEBUG quickenv] attempting to launch shim for "[scrubbed $HOME]/.quickenv/bin/hello"
+    [DEBUG quickenv] loading [scrubbed $HOME]/project/.envrc
+    [DEBUG quickenv] removing own entry from PATH: [scrubbed $HOME]/.quickenv/bin
+    [DEBUG quickenv] removing own entry from PATH: [scrubbed $HOME]/.quickenv/bin
+    [ERROR quickenv] failed to run shimmed command
+
+    Caused by:
+        0: failed to run hello
+        1: failed to find actual binary
+        2: failed to find hello
+        3: cannot find binary path
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_which() -> Result<(), Error> {
+    let harness = setup()?;
+
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "which" "hello", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] cannot find binary path
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert_cmd!(harness, quickenv "which" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    bogus/hello
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_which_pretend_shimmed() -> Result<(), Error> {
+    let harness = setup()?;
+
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+
+    assert_cmd!(harness, quickenv "which" "bash", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] "bash" is not shimmed by quickenv
+    "###);
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+
+    assert_cmd!(harness, quickenv "which" "bash", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] "bash" is not shimmed by quickenv
+    "###);
+
+    write(harness.join("bogus/bash"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/bash"))?;
+
+    assert_cmd!(harness, quickenv "which" "bash", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] "bash" is not shimmed by quickenv
+    "###);
+
+    assert_cmd!(harness, quickenv "which" "bash" "--pretend-shimmed", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    bogus/bash
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_which_no_shim() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+    harness.trust()?;
+    for dir in ["bogus", "ambient"] {
+        create_dir_all(harness.join(dir))?;
+        for program in ["hello", "world"] {
+            write(harness.join(dir).join(program), "#!/bin/sh\necho hello")?;
+            set_executable(harness.join(dir).join(program))?;
+        }
+    }
+    // after the shims, such that it does not shadow them
+    let mut path = harness.var("PATH").unwrap().to_owned();
+    path.push(":");
+    path.push(harness.join("ambient"));
+    harness.set_var("PATH", &path);
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "1");
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
+    let output = harness.quickenv_output(&["shim", "hello"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    assert_cmd!(harness, quickenv "which" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    bogus/hello
+
+    ----- stderr -----
+    "###);
+
+    // the .envrc is not applied, and neither is the check for whether a shim exists
+    harness.set_var("QUICKENV_NO_SHIM", "1");
+    assert_cmd!(harness, quickenv "which" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [scrubbed $HOME]/project/ambient/hello
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "which" "world", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [scrubbed $HOME]/project/ambient/world
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_reload_quiet() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "echo hello from envrc\nexport MYVALUE=canary\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload" "--quiet", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=canary
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_project_log_level() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+    harness.trust()?;
+    write(harness.join(".quickenv.toml"), "log_level = \"debug\"\n")?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [DEBUG quickenv] argv[0] is "[scrubbed $HOME]/.quickenv/quickenv_bin/quickenv"
+    [DEBUG quickenv] own program name is quickenv, so no shim running
+    [DEBUG quickenv] loading [scrubbed $HOME]/project/.envrc
+    [DEBUG quickenv] loading [scrubbed $HOME]/project/.envrc
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [DEBUG quickenv] argv[0] is "[scrubbed $HOME]/.quickenv/quickenv_bin/quickenv"
+    [DEBUG quickenv] own program name is quickenv, so no shim running
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello world
+
+    ----- stderr -----
+    [DEBUG quickenv] argv[0] is "[scrubbed $HOME]/.quickenv/bin/hello"
+    [DEBUG quickenv] attempting to launch shim for "[scrubbed $HOME]/.quickenv/bin/hello"
+    [DEBUG quickenv] loading [scrubbed $HOME]/project/.envrc
+    [DEBUG quickenv] removing own entry from PATH: [scrubbed $HOME]/.quickenv/bin
+    [DEBUG quickenv] loading [scrubbed $HOME]/project/.envrc
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_project_log_level_env_override() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "")?;
+    harness.trust()?;
+    write(harness.join(".quickenv.toml"), "log_level = \"debug\"\n")?;
+    harness.set_var("QUICKENV_LOG", "info");
+
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: false
+    exit_code: 4
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] Run 'quickenv reload' first to generate envvars
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_missing_shims_skips_bad_path_entries() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export PATH=bogus:not-a-dir:does-not-exist:$PATH\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+    write(harness.join("not-a-dir"), "")?;
+
+    harness.set_var("QUICKENV_LOG", "debug");
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [DEBUG quickenv] argv[0] is "[scrubbed $HOME]/.quickenv/quickenv_bin/quickenv"
+    [DEBUG quickenv] own program name is quickenv, so no shim running
+    [DEBUG quickenv] loading [scrubbed $HOME]/project/.envrc
+    [DEBUG quickenv] loading [scrubbed $HOME]/project/.envrc
+    [DEBUG quickenv] skipping over PATH entry [scrubbed $HOME]/project/not-a-dir (not a directory): Not a directory (os error 20)
+    [DEBUG quickenv] skipping over PATH entry does-not-exist (does not exist): No such file or directory (os error 2)
+    [DEBUG quickenv] skipped 2 inaccessible PATH entries
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_shim_quiet() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+    write(harness.join("bogus/hello2"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello2"))?;
+
+    assert_cmd!(harness, quickenv "reload" "--quiet", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 2 unshimmed commands (2 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "-y" "--quiet" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    harness.which("hello")?;
+    assert_cmd!(harness, quickenv "shim" "-y" "--quiet", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    harness.which("hello2")?;
+    Ok(())
+}
+
+#[test]
+fn test_spoofed_markers() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "echo '// BEGIN QUICKENV-AFTER'\necho '// END QUICKENV-AFTER'\nexport MYVALUE=canary\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    // BEGIN QUICKENV-AFTER
+    // END QUICKENV-AFTER
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=canary
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_custom_bin_dir() -> Result<(), Error> {
+    let mut harness = setup()?;
+    let bin_dir = Path::new(harness.var("HOME").unwrap()).join(".local/bin");
+    create_dir_all(&bin_dir)?;
+    harness.prepend_path(&bin_dir);
+    harness.set_var("QUICKENV_BIN_DIR", &bin_dir);
+
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.local/bin.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert!(bin_dir.join("hello").exists());
+    assert!(!harness.join("../.quickenv/bin/hello").exists());
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello world
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "which" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    bogus/hello
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "unshim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Removed 1 shims from [scrubbed $HOME]/.local/bin.
+    Use 'quickenv shim <command>' to add them again
+    "###);
+    assert!(!bin_dir.join("hello").exists());
+    Ok(())
+}
+
+#[test]
+fn test_project_shim_warnings_disabled() -> Result<(), Error> {
+    let mut harness = setup()?;
+    let project = harness.cwd.clone();
+    let other_project = harness.join("../other");
+
+    for dir in [&project, &other_project] {
+        create_dir_all(dir.join("bogus"))?;
+        write(dir.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+        write(dir.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+        set_executable(dir.join("bogus/hello"))?;
+    }
+    write(project.join(".quickenv.toml"), "shim_warnings = false\n")?;
+
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+
+    harness.cwd = other_project;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+
+    harness.cwd = project;
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "0");
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands. Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_invalid_project_config() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=hello\n")?;
+    write(harness.join(".quickenv.toml"), "shim_warnings = \"no\"\n")?;
+    harness.trust()?;
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "1");
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    assert_cmd!(harness, quickenv "exec" "--" "sh" "-c" "echo $MYVALUE", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello
+
+    ----- stderr -----
+    [WARN quickenv] failed to parse [scrubbed $HOME]/project/.quickenv.toml, using the default config: TOML parse error at line 1, column 17
+      |
+    1 | shim_warnings = "no"
+      |                 ^^^^
+    invalid type: string "no", expected a boolean
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_status_nested() -> Result<(), Error> {
+    let mut harness = setup()?;
+    create_dir_all(harness.join("sub/deeper"))?;
+    write(harness.join(".envrc"), "export OUTER=1\n")?;
+    write(harness.join("sub/.envrc"), "export INNER=1\n")?;
+    harness.cwd = harness.join("sub/deeper");
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "status", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    .envrc: [scrubbed $HOME]/project/sub/.envrc
+    cache: [scrubbed $HOME]/.quickenv/envs/[cache key] (missing, run 'quickenv reload')
+    key: [cache key]
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars" "--annotate", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    # [scrubbed $HOME]/project/sub/.envrc
+    INNER=1
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_shim_list_candidates() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    for name in ["hello", "hello2"] {
+        write(
+            harness.join("bogus").join(name),
+            "#!/bin/sh\necho hello world",
+        )?;
+        set_executable(harness.join("bogus").join(name))?;
+    }
+
+    assert_cmd!(harness, quickenv "reload" "--quiet", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 2 unshimmed commands (2 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "--list-candidates", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello2
+
+    ----- stderr -----
+    "###);
+    harness.which("hello2").unwrap_err();
+    Ok(())
+}
+
+#[test]
+fn test_trust() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "echo evaluating\nexport MYVALUE=1\n",
+    )?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 7
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] [scrubbed $HOME]/project/.envrc is not trusted. Review its contents, then run 'quickenv trust' to allow executing it
+    "###);
+    assert_cmd!(harness, quickenv "trust", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Trusted [scrubbed $HOME]/project/.envrc. Use 'quickenv reload' to load it.
+    "###);
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    evaluating
+
+    ----- stderr -----
+    "###);
+
+    // editing the .envrc requires trusting it again
+    write(
+        harness.join(".envrc"),
+        "echo evaluating\nexport MYVALUE=2\n",
+    )?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 7
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] [scrubbed $HOME]/project/.envrc is not trusted. Review its contents, then run 'quickenv trust' to allow executing it
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=1
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "trust", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Trusted [scrubbed $HOME]/project/.envrc. Use 'quickenv reload' to load it.
+    "###);
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    evaluating
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=2
+
+    ----- stderr -----
+    "###);
+
+    assert_cmd!(harness, quickenv "untrust", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Untrusted [scrubbed $HOME]/project/.envrc.
+    "###);
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 7
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] [scrubbed $HOME]/project/.envrc is not trusted. Review its contents, then run 'quickenv trust' to allow executing it
+    "###);
+    assert_cmd!(harness, quickenv "untrust", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] [scrubbed $HOME]/project/.envrc was not trusted.
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_reload_path() -> Result<(), Error> {
+    let mut harness = setup()?;
+    create_dir_all(harness.join("sub"))?;
+    write(
+        harness.join(".envrc"),
+        "echo evaluating in $PWD\nexport MYVALUE=1\n",
+    )?;
+    harness.trust()?;
+
+    harness.cwd = harness.join("..");
+    assert_cmd!(harness, quickenv "reload" "./project", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    evaluating in [scrubbed $HOME]/project
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "reload" "project/sub", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    evaluating in [scrubbed $HOME]/project
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "reload" "does-not-exist", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to change directory to does-not-exist
+
+    Caused by:
+        No such file or directory (os error 2)
+    "###);
+
+    harness.cwd = harness.join("project");
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=1
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_shim_bin_dir_not_on_path() -> Result<(), Error> {
+    let mut harness = setup()?;
+    let bin_dir = Path::new(harness.var("HOME").unwrap()).join("not-on-path");
+    harness.set_var("QUICKENV_BIN_DIR", &bin_dir);
+
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to find command hello after shimming. Are you sure that [scrubbed $HOME]/not-on-path is on your PATH?
+
+    Caused by:
+        cannot find binary path
+    "###);
+
+    assert_cmd!(harness, quickenv "shim" "--no-verify" "hello" "world", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/not-on-path.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert!(bin_dir.join("hello").exists());
+    assert!(bin_dir.join("world").exists());
+    Ok(())
+}
+
+#[test]
+fn test_vars_null() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export MULTILINE=\"hello\nworld\"\nexport OTHER=1\n",
+    )?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+
+    let output = harness.quickenv_output(&["vars", "--null"])?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"MULTILINE=hello\nworld\0OTHER=1\0");
+    Ok(())
+}
+
+#[test]
+fn test_git_root() -> Result<(), Error> {
+    let mut harness = setup()?;
+    create_dir_all(harness.join(".git"))?;
+    create_dir_all(harness.join("sub/deeper"))?;
+    write(harness.join(".envrc"), "export WHICH=root\n")?;
+    write(harness.join("sub/.envrc"), "export WHICH=sub\n")?;
+    harness.trust()?;
+
+    harness.cwd = harness.join("sub/deeper");
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload" "--git-root", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars" "--git-root", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    WHICH=root
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: false
+    exit_code: 4
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] Run 'quickenv reload' first to generate envvars
+    "###);
+    assert_cmd!(harness, quickenv "exec" "--git-root" "sh" "-c" "echo $WHICH; pwd", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    root
+    [scrubbed $HOME]/project/sub/deeper
+
+    ----- stderr -----
+    "###);
+
+    harness.cwd = harness.join("../../..");
+    assert_cmd!(harness, quickenv "vars" "--git-root", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to find a git repository in current or any parent directory
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_search_boundary() -> Result<(), Error> {
+    let mut harness = setup()?;
+    // a stray .envrc in $HOME, and a project without one
+    write(harness.join("../.envrc"), "export WHICH=home\n")?;
+    create_dir_all(harness.join(".git"))?;
+    create_dir_all(harness.join("sub"))?;
+    harness.cwd = harness.join("sub");
+
+    assert_cmd!(harness, quickenv "status", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    .envrc: [scrubbed $HOME]/.envrc
+    cache: [scrubbed $HOME]/.quickenv/envs/[cache key] (missing, run 'quickenv reload')
+    key: [cache key]
+
+    ----- stderr -----
+    "###);
+
+    harness.set_var("QUICKENV_SEARCH_BOUNDARY", "git");
+    assert_cmd!(harness, quickenv "status", @r###"
+    success: false
+    exit_code: 3
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to find .envrc in current or any parent directory
+    "###);
+
+    // directories can be excluded as well, unless the search starts there
+    let home = harness.join("../..");
+    harness.set_var("QUICKENV_SEARCH_BOUNDARY", &home);
+    assert_cmd!(harness, quickenv "status", @r###"
+    success: false
+    exit_code: 3
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to find .envrc in current or any parent directory
+    "###);
+    harness.cwd = home;
+    assert_cmd!(harness, quickenv "status", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    .envrc: [scrubbed $HOME]/.envrc
+    cache: [scrubbed $HOME]/.quickenv/envs/[cache key] (missing, run 'quickenv reload')
+    key: [cache key]
+
+    ----- stderr -----
+    "###);
+
+    harness.set_var("QUICKENV_SEARCH_BOUNDARY", "project");
+    assert_cmd!(harness, quickenv "status", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] invalid entry "project" in QUICKENV_SEARCH_BOUNDARY, expected 'git' or an absolute path
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_git_root_linked_worktree() -> Result<(), Error> {
+    let mut harness = setup()?;
+    create_dir_all(harness.join(".git/worktrees/wt"))?;
+    write(harness.join(".envrc"), "export WHICH=main\n")?;
+    harness.trust()?;
+
+    // in a linked worktree, .git is a file pointing back into the main repository
+    let worktree = harness.join("../wt");
+    create_dir_all(worktree.join("sub"))?;
+    write(
+        worktree.join(".git"),
+        format!("gitdir: {}\n", harness.join(".git/worktrees/wt").display()),
+    )?;
+    write(worktree.join(".envrc"), "export WHICH=worktree\n")?;
+    std::os::unix::fs::symlink(&worktree, harness.join("../wt-link"))?;
+
+    let link = harness.join("../wt-link/sub");
+    let real_cwd = std::mem::replace(&mut harness.cwd, link);
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload" "--git-root", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "resolve", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    envrc: [scrubbed $HOME]/wt/.envrc
+    root: [scrubbed $HOME]/wt
+    cache: [scrubbed $HOME]/.quickenv/envs/[cache key]
+    key: [cache key]
+    cache_exists: true
+    cache_fresh: true
+
+    ----- stderr -----
+    "###);
+
+    harness.cwd = worktree.join("sub");
+    assert_cmd!(harness, quickenv "vars" "--git-root", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    WHICH=worktree
+
+    ----- stderr -----
+    "###);
+
+    let caches = std::fs::read_dir(real_cwd.join("../.quickenv/envs"))?
+        .filter(|entry| entry.as_ref().unwrap().path().extension().is_none())
+        .count();
+    assert_eq!(caches, 1);
+    Ok(())
+}
+
+#[test]
+fn test_vars_exit_codes() -> Result<(), Error> {
+    let harness = setup()?;
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: false
+    exit_code: 3
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to find .envrc in current or any parent directory
+    "###);
+
+    write(harness.join(".envrc"), "export MYVALUE=1\n")?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: false
+    exit_code: 4
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] Run 'quickenv reload' first to generate envvars
+    "###);
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=1
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_unshim_glob() -> Result<(), Error> {
+    let harness = setup()?;
+    create_dir_all(harness.join("bogus"))?;
+    for name in ["cargo", "cargo-clippy", "rustc"] {
+        write(
+            harness.join("bogus").join(name),
+            "#!/bin/sh\necho hello world",
+        )?;
+        set_executable(harness.join("bogus").join(name))?;
+    }
+    // not a shim, but matches the pattern
+    write(harness.join("../.quickenv/bin/cargo-fake"), "")?;
+
+    assert_cmd!(harness, quickenv "shim" "cargo" "cargo-clippy" "rustc", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 3 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert_cmd!(harness, quickenv "unshim" "cargo*", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Removed 2 shims from [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv shim <command>' to add them again
+    "###);
+    assert!(!harness.join("../.quickenv/bin/cargo").exists());
+    assert!(!harness.join("../.quickenv/bin/cargo-clippy").exists());
+    assert!(harness.join("../.quickenv/bin/cargo-fake").exists());
+    harness.which("rustc")?;
+
+    assert_cmd!(harness, quickenv "unshim" "cargo*", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] "cargo*" did not match any shims
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_shellenv() -> Result<(), Error> {
+    let mut harness = setup()?;
+
+    assert_cmd!(harness, quickenv "shellenv" "bash", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    export PATH="[scrubbed $HOME]/.quickenv/bin:$PATH"
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "shellenv" "fish", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    fish_add_path --move --path '[scrubbed $HOME]/.quickenv/bin'
+
+    ----- stderr -----
+    "###);
+
+    let bin_dir = harness.join("../custom bin");
+    harness.set_var("QUICKENV_BIN_DIR", &bin_dir);
+    let output = harness.quickenv_output(&["shellenv", "zsh"])?;
+    assert!(output.status.success());
+    let line = String::from_utf8(output.stdout)?;
+    assert!(line.contains(bin_dir.to_str().unwrap()), "{}", line);
+    Ok(())
+}
+
+#[test]
+fn test_self_path() -> Result<(), Error> {
+    let harness = setup()?;
+
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+
+    let output = harness.quickenv_output(&["self-path"])?;
+    assert!(output.status.success());
+    let self_path = String::from_utf8(output.stdout)?;
+    // shims point to the symlink quickenv was run through, so they survive upgrades that only
+    // change where it points to
+    let installed_binary =
+        Path::new(harness.var("HOME").unwrap()).join(".quickenv/quickenv_bin/quickenv");
+    assert_eq!(self_path.trim_end(), installed_binary.to_str().unwrap());
+    assert_eq!(
+        std::fs::read_link(harness.join("../.quickenv/bin/hello"))?,
+        installed_binary
+    );
+    Ok(())
+}
+
+#[test]
+fn test_prelude_overrides_echo() -> Result<(), Error> {
+    let mut harness = setup()?;
+    // a shell whose echo mangles its output should not break the env diff
+    harness.set_var(
+        "QUICKENV_PRELUDE",
+        "echo() { builtin echo \"mangled: $*\"; }",
+    );
+    write(
+        harness.join(".envrc"),
+        "echo hello\nexport MYVALUE=canary\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    mangled: hello
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=canary
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_exec_cwd() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    harness.trust()?;
+    create_dir_all(harness.join("../elsewhere"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "exec" "--cwd" "../elsewhere" "--" "sh" "-c" "pwd; echo $MYVALUE", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [scrubbed $HOME]/elsewhere
+    canary
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "exec" "--cwd" "../nonexistent" "--" "true", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] [scrubbed $HOME]/project/../nonexistent is not a directory
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_list() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    std::os::unix::fs::symlink(
+        "../nonexistent/quickenv",
+        harness.join("../.quickenv/bin/dangling"),
+    )?;
+
+    assert_cmd!(harness, quickenv "list", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    dangling (broken, [scrubbed $HOME]/.quickenv/bin/../nonexistent/quickenv is missing)
+    hello
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "list" "--json", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [
+      {
+        "name": "dangling",
+        "name_base64": false,
+        "target": "[scrubbed $HOME]/.quickenv/bin/../nonexistent/quickenv",
+        "valid": false,
+        "resolved": null
+      },
+      {
+        "name": "hello",
+        "name_base64": false,
+        "target": "[scrubbed $HOME]/.quickenv/quickenv_bin/quickenv",
+        "valid": true,
+        "resolved": "bogus/hello"
+      }
+    ]
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_reload_unchanged_cache() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    let cache_path = std::fs::read_dir(harness.join("../.quickenv/envs"))?
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_none())
+        .unwrap();
+    let mtime = std::fs::metadata(&cache_path)?.modified()?;
+
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_eq!(std::fs::metadata(&cache_path)?.modified()?, mtime);
+
+    write(harness.join(".envrc"), "export MYVALUE=canary2\n")?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_ne!(std::fs::metadata(&cache_path)?.modified()?, mtime);
+    Ok(())
+}
+
+#[test]
+fn test_reload_duplicate_vars() -> Result<(), Error> {
+    let mut harness = setup()?;
+    // env prints the newline in the value, making MYVALUE appear twice
+    write(
+        harness.join(".envrc"),
+        "export MYVALUE=$'first\\nMYVALUE=last'\n",
+    )?;
+    harness.trust()?;
+
+    harness.set_var("QUICKENV_LOG", "quickenv::core=debug");
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("\"MYVALUE\" is set more than once, using its last value"),
+        "{}",
+        stderr
+    );
+
+    harness.set_var("QUICKENV_LOG", "error");
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=last
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_idle_timeout() -> Result<(), Error> {
+    let mut harness = setup()?;
+    harness.set_var("QUICKENV_IDLE_TIMEOUT", "0.5");
+
+    // slow, but keeps printing progress
+    write(
+        harness.join(".envrc"),
+        "for i in 1 2 3 4 5 6; do echo progress; sleep 0.2; done\nexport MYVALUE=canary\n",
+    )?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    progress
+    progress
+    progress
+    progress
+    progress
+    progress
+
+    ----- stderr -----
+    "###);
+
+    // goes silent
+    write(
+        harness.join(".envrc"),
+        "echo starting\nwhile true; do :; done\n",
+    )?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+    starting
+
+    ----- stderr -----
+    [ERROR quickenv] .envrc printed nothing for 500ms, killed it (QUICKENV_IDLE_TIMEOUT)
+    "###);
+    // recorded like any other failure, such that shims and doctor report it
+    let output = harness.quickenv_output(&["doctor"])?;
+    let output = String::from_utf8(output.stdout)? + &String::from_utf8(output.stderr)?;
+    assert!(
+        output.contains("the last reload failed (.envrc printed nothing for 500ms"),
+        "{}",
+        output
+    );
+
+    harness.set_var("QUICKENV_IDLE_TIMEOUT", "soon");
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] invalid QUICKENV_IDLE_TIMEOUT "soon", expected seconds
+    "###);
+
+    // too large to be represented as a duration
+    harness.set_var("QUICKENV_IDLE_TIMEOUT", "1e20");
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] invalid QUICKENV_IDLE_TIMEOUT "1e20", expected seconds
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_shim_opts() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(
+        harness.join("bogus/hello"),
+        "#!/bin/sh\necho hello $MYVALUE",
+    )?;
+    set_executable(harness.join("bogus/hello"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+
+    // use a minimal PATH to keep the debug output stable, with the program behind the shim
+    let original_path = harness.var("PATH").unwrap().to_owned();
+    let home = Path::new(harness.var("HOME").unwrap()).to_owned();
+    harness.set_var(
+        "PATH",
+        std::env::join_paths([
+            home.join(".quickenv/quickenv_bin"),
+            home.join(".quickenv/bin"),
+            harness.join("bogus"),
+        ])?,
+    );
+    harness.set_var("QUICKENV_SHIM_OPTS", "exec,debug bogus");
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello canary
+
+    ----- stderr -----
+    [DEBUG quickenv] argv[0] is "[scrubbed $HOME]/.quickenv/bin/hello"
+    [DEBUG quickenv] attempting to launch shim for "[scrubbed $HOME]/.quickenv/bin/hello"
+    [WARN quickenv] ignoring unknown option "bogus" in QUICKENV_SHIM_OPTS
+    [DEBUG quickenv] loading [scrubbed $HOME]/project/.envrc
+    [DEBUG quickenv] removing own entry from PATH: [scrubbed $HOME]/.quickenv/bin
+    [DEBUG quickenv] export "MYVALUE"="canary"
+    [DEBUG quickenv] export "PATH"="[scrubbed $HOME]/.quickenv/quickenv_bin:[scrubbed $HOME]/project/bogus"
+    [DEBUG quickenv] export "QUICKENV_NO_SHIM"="[scrubbed $HOME]/project/.envrc"
+    [DEBUG quickenv] execvp [scrubbed $HOME]/project/bogus/hello
+    "###);
+
+    harness.set_var("PATH", original_path);
+    harness.set_var("QUICKENV_SHIM_OPTS", "no-env");
+    assert_cmd!(harness, quickenv "exec" "--" "sh" "-c" "echo ${MYVALUE:-unset}", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    unset
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_reload_print() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "echo hello\nexport MYVALUE=canary\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload" "--print", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=canary
+
+    ----- stderr -----
+    hello
+    "###);
+    assert!(!harness.join("../.quickenv/envs").exists());
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: false
+    exit_code: 4
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] Run 'quickenv reload' first to generate envvars
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_reload_from() -> Result<(), Error> {
+    let harness = setup()?;
+    // neither run nor required to be trusted
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+
+    assert_cmd!(harness, quickenv "reload" "--from" "export FOO=bar", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    FOO=bar
+
+    ----- stderr -----
+    "###);
+    assert!(!harness.join("../.quickenv/envs").exists());
+
+    // shims would pick up the cached result, so the project needs to be trusted for that
+    assert_cmd!(harness, quickenv "reload" "--from" "export FOO=baz" "--cache", @r###"
+    success: false
+    exit_code: 7
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] [scrubbed $HOME]/project/.envrc is not trusted. Review its contents, then run 'quickenv trust' to allow executing it
+    "###);
+    assert!(!harness.join("../.quickenv/envs").exists());
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload" "--from" "export FOO=baz" "--cache", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    FOO=baz
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_xdg_dirs() -> Result<(), Error> {
+    let mut harness = setup()?;
+    let home = Path::new(harness.var("HOME").unwrap()).to_owned();
+
+    // get rid of the legacy ~/.quickenv, but keep quickenv itself on PATH
+    create_dir_all(home.join("quickenv_bin"))?;
+    std::os::unix::fs::symlink(
+        std::fs::canonicalize("target/debug/quickenv")?,
+        home.join("quickenv_bin/quickenv"),
+    )?;
+    std::fs::remove_dir_all(home.join(".quickenv"))?;
+    harness.prepend_path(home.join("xdg-data/quickenv/bin"));
+    harness.prepend_path(home.join("quickenv_bin"));
+    harness.set_var("XDG_DATA_HOME", home.join("xdg-data"));
+    harness.set_var("XDG_CACHE_HOME", home.join("xdg-cache"));
+
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "status", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    .envrc: [scrubbed $HOME]/project/.envrc
+    cache: [scrubbed $HOME]/xdg-cache/quickenv/envs/[cache key]
+    key: [cache key]
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/xdg-data/quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+
+    assert!(home.join("xdg-data/quickenv/bin/hello").exists());
+    assert!(home.join("xdg-data/quickenv/trusted").is_dir());
+    assert!(home.join("xdg-cache/quickenv/envs").is_dir());
+    assert!(!home.join(".quickenv").exists());
+    assert!(!home.join(".local").exists());
+    Ok(())
+}
+
+#[test]
+fn test_migrate() -> Result<(), Error> {
+    let mut harness = setup()?;
+    let home = Path::new(harness.var("HOME").unwrap()).to_owned();
+
+    // keep quickenv itself on PATH outside of the legacy ~/.quickenv
+    create_dir_all(home.join("quickenv_bin"))?;
+    std::os::unix::fs::symlink(
+        std::fs::canonicalize("target/debug/quickenv")?,
+        home.join("quickenv_bin/quickenv"),
+    )?;
+    std::fs::remove_dir_all(home.join(".quickenv/quickenv_bin"))?;
+    harness.prepend_path(home.join(".local/share/quickenv/bin"));
+    harness.prepend_path(home.join("quickenv_bin"));
+
+    write(
+        harness.join(".envrc"),
+        "export PATH=bogus:$PATH\nexport MYVALUE=canary\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(
+        harness.join("bogus/hello"),
+        "#!/bin/sh\necho hello $MYVALUE",
+    )?;
+    set_executable(harness.join("bogus/hello"))?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+
+    assert_cmd!(harness, quickenv "migrate" "--dry-run", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [scrubbed $HOME]/.quickenv/envs/[cache key] -> [scrubbed $HOME]/.cache/quickenv/envs/[cache key]
+    [scrubbed $HOME]/.quickenv/envs/[cache key].reloaded -> [scrubbed $HOME]/.cache/quickenv/envs/[cache key].reloaded
+    [scrubbed $HOME]/.quickenv/trusted/[cache key] -> [scrubbed $HOME]/.local/share/quickenv/trusted/[cache key]
+    [scrubbed $HOME]/.quickenv/bin/hello -> [scrubbed $HOME]/.local/share/quickenv/bin/hello
+
+    ----- stderr -----
+    "###);
+    assert!(home.join(".quickenv/bin/hello").exists());
+
+    assert_cmd!(harness, quickenv "migrate", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Migrated 4 files to [scrubbed $HOME]/.local/share/quickenv/.
+    Update your PATH to contain [scrubbed $HOME]/.local/share/quickenv/bin/ instead of [scrubbed $HOME]/.quickenv/bin/
+    "###);
+    assert!(!home.join(".quickenv").exists());
+    assert_eq!(
+        harness.which("hello")?,
+        home.join(".local/share/quickenv/bin/hello")
+    );
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello canary
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "migrate", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Nothing to migrate, [scrubbed $HOME]/.quickenv/ does not exist.
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_which_all_json() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=bogus:bogus2:$PATH\n")?;
+    harness.trust()?;
+    for dir in ["bogus", "bogus2"] {
+        create_dir_all(harness.join(dir))?;
+        write(
+            harness.join(dir).join("hello"),
+            "#!/bin/sh\necho hello world",
+        )?;
+        set_executable(harness.join(dir).join("hello"))?;
+    }
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert_cmd!(harness, quickenv "which" "--all" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    bogus/hello
+    bogus2/hello
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "which" "--json" "--all" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [
+      {
+        "program": "hello",
+        "winner": "bogus/hello",
+        "candidates": [
+          "bogus/hello",
+          "bogus2/hello"
+        ]
+      }
+    ]
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_which_all_skips_shims() -> Result<(), Error> {
+    let mut harness = setup()?;
+    // another directory of shims, such as from a different QUICKENV_BIN_DIR
+    write(
+        harness.join(".envrc"),
+        "export PATH=othershims:bogus:$PATH\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("othershims"))?;
+    std::os::unix::fs::symlink(
+        std::fs::canonicalize("target/debug/quickenv")?,
+        harness.join("othershims/hello"),
+    )?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "1");
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    assert_cmd!(harness, quickenv "which" "--pretend-shimmed" "--all" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    bogus/hello
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_shim_after_failed_reload() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export PATH=bogus:$PATH\nexport MYVALUE=canary\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(
+        harness.join("bogus/hello"),
+        "#!/bin/sh\necho hello $MYVALUE",
+    )?;
+    set_executable(harness.join("bogus/hello"))?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+
+    write(
+        harness.join(".envrc"),
+        "export PATH=bogus:$PATH\nexport MYVALUE=canary2\nexit 3\n",
+    )?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 5
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] .envrc exited with status exit status: 3
+    "###);
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello canary
+
+    ----- stderr -----
+    [WARN quickenv] the last 'quickenv reload' of [scrubbed $HOME]/project/.envrc failed: .envrc exited with status exit status: 3
+    "###);
+
+    write(
+        harness.join(".envrc"),
+        "export PATH=bogus:$PATH\nexport MYVALUE=canary2\n",
+    )?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello canary2
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_vars_cache_file() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+
+    let cache_path = std::fs::read_dir(harness.join("../.quickenv/envs"))?
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_none())
+        .unwrap();
+    std::fs::copy(&cache_path, harness.join("../copied-cache"))?;
+    write(harness.join("../garbage"), "garbage\n")?;
+
+    // no .envrc is needed to read a cache by path
+    harness.cwd = harness.join("..");
+    assert_cmd!(harness, quickenv "vars" "--annotate" "--cache-file" "copied-cache", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    # copied-cache
+    MYVALUE=canary
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars" "--cache-file" "garbage", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to read cache file garbage
+
+    Caused by:
+        garbage is not a valid quickenv cache file
+    "###);
+    assert_cmd!(harness, quickenv "vars" "--cache-file" "nonexistent", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to read cache file nonexistent
+
+    Caused by:
+        No such file or directory (os error 2)
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_which_multiple() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    for name in ["hello", "world", "foo"] {
+        write(
+            harness.join("bogus").join(name),
+            "#!/bin/sh\necho hello world",
+        )?;
+        set_executable(harness.join("bogus").join(name))?;
+    }
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 3 unshimmed commands (3 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello" "world" "foo", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 3 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert_cmd!(harness, quickenv "which" "hello" "world" "foo", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    bogus/hello
+    bogus/world
+    bogus/foo
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "which" "--all" "hello" "world", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello: bogus/hello
+    world: bogus/world
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "which" "--json" "hello" "world" "foo" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [
+      {
+        "program": "hello",
+        "winner": "bogus/hello",
+        "candidates": [
+          "bogus/hello"
+        ]
+      },
+      {
+        "program": "world",
+        "winner": "bogus/world",
+        "candidates": [
+          "bogus/world"
+        ]
+      },
+      {
+        "program": "foo",
+        "winner": "bogus/foo",
+        "candidates": [
+          "bogus/foo"
+        ]
+      },
+      {
+        "program": "hello",
+        "winner": "bogus/hello",
+        "candidates": [
+          "bogus/hello"
+        ]
+      }
+    ]
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_native_before_env() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export PATH=bogus:$PATH\nexport MYVALUE=canary\nexport HOME=\"$HOME\"\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("subdir"))?;
+    harness.cwd = harness.join("subdir");
+
+    let output = harness.quickenv_output(&["reload", "--print"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    harness.set_var("QUICKENV_NATIVE_BEFORE_ENV", "1");
+    let native_output = harness.quickenv_output(&["reload", "--print"])?;
+    assert!(native_output.status.success(), "{:?}", native_output);
+
+    assert_eq!(
+        String::from_utf8(native_output.stdout)?,
+        String::from_utf8(output.stdout)?
+    );
+    Ok(())
+}
+
+#[test]
+fn test_reload_watch() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=first\n")?;
+    harness.trust()?;
+
+    let wait_for_value = |value: &str| -> Result<(), Error> {
+        for _ in 0..100 {
+            let output = harness.quickenv_output(&["vars"])?;
+            if String::from_utf8(output.stdout)? == format!("MYVALUE={value}\n") {
+                return Ok(());
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        panic!("cache did not get updated to {value}");
+    };
+
+    let mut child = std::process::Command::new(harness.which("quickenv")?)
+        .arg("reload")
+        .arg("--watch")
+        .current_dir(&harness.cwd)
+        .env_remove("QUICKENV_PRELUDE")
+        .envs(&harness.env)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+
+    let result = wait_for_value("first").and_then(|()| {
+        write(harness.join(".envrc"), "export MYVALUE=second\n")?;
+        harness.trust()?;
+        wait_for_value("second")
+    });
+    child.kill()?;
+    child.wait()?;
+    result
+}
+
+#[test]
+fn test_resolve() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    harness.trust()?;
+    create_dir_all(harness.join("subdir"))?;
+
+    assert_cmd!(harness, quickenv "resolve" "--json", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    {
+      "envrc": "[scrubbed $HOME]/project/.envrc",
+      "root": "[scrubbed $HOME]/project",
+      "cache": "[scrubbed $HOME]/.quickenv/envs/[cache key]",
+      "key": "[cache key]",
+      "cache_exists": false,
+      "cache_fresh": false
+    }
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "resolve" "--json" "--dir" "subdir", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    {
+      "envrc": "[scrubbed $HOME]/project/.envrc",
+      "root": "[scrubbed $HOME]/project",
+      "cache": "[scrubbed $HOME]/.quickenv/envs/[cache key]",
+      "key": "[cache key]",
+      "cache_exists": true,
+      "cache_fresh": true
+    }
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "resolve", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    envrc: [scrubbed $HOME]/project/.envrc
+    root: [scrubbed $HOME]/project
+    cache: [scrubbed $HOME]/.quickenv/envs/[cache key]
+    key: [cache key]
+    cache_exists: true
+    cache_fresh: true
+
+    ----- stderr -----
+    "###);
+
+    // the key is the filename of the cache, so CI can cache it by that name
+    let output = harness.quickenv_output(&["resolve"])?;
+    let stdout = String::from_utf8(output.stdout)?;
+    let field = |name: &str| {
+        stdout
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+            .unwrap()
+            .to_owned()
+    };
+    let cache = Path::new(&field("cache")).to_owned();
+    assert!(cache.exists());
+    assert_eq!(cache.file_name().unwrap().to_str(), Some(&*field("key")));
+
+    assert_cmd!(harness, quickenv "resolve" "--dir" "..", @r###"
+    success: false
+    exit_code: 3
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to find .envrc in current or any parent directory
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_color() -> Result<(), Error> {
+    let mut harness = setup()?;
+    // would otherwise enable colors even though stderr is not a terminal
+    harness.set_var("CLICOLOR_FORCE", "1");
+
+    let output = harness.quickenv_output(&["--color=never", "unshim", "quickenv"])?;
+    assert!(output.status.success());
+    assert!(!output.stderr.contains(&b'\x1b'), "{:?}", output);
+
+    harness.set_var("CLICOLOR_FORCE", "0");
+    let output = harness.quickenv_output(&["unshim", "quickenv", "--color=always"])?;
+    assert!(output.status.success());
+    assert!(output.stderr.contains(&b'\x1b'), "{:?}", output);
+    Ok(())
+}
+
+#[test]
+fn test_direnv_missing() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    harness.trust()?;
+
+    let quickenv_bin = harness.which("quickenv")?.parent().unwrap().to_owned();
+    harness.set_var("PATH", &quickenv_bin);
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] direnv not found on PATH. Install it, or set QUICKENV_PRELUDE to something that does not require it, such as QUICKENV_PRELUDE=''
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_envrc_cd() -> Result<(), Error> {
+    let harness = setup()?;
+    create_dir_all(harness.join("subdir"))?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+    write(
+        harness.join(".envrc"),
+        "cd subdir\nexport MYVALUE=$PWD\ncd ..\nexport PATH=bogus:$PATH\ncd subdir\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    // the variables describing bash's working directory are not cached
+    let output = harness.quickenv_output(&["vars"])?;
+    let names: Vec<_> = output
+        .stdout
+        .split(|&c| c == b'\n')
+        .filter_map(|line| line.split(|&c| c == b'=').next())
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect();
+    assert_eq!(names, ["MYVALUE", "PATH"]);
+    assert_cmd!(harness, quickenv "exec" "--" "sh" "-c" "echo $MYVALUE", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [scrubbed $HOME]/project/subdir
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "exec" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello world
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_unshim_prune_broken() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=bogus:$PATH\n")?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+    write(
+        harness.join("bogus/goodbye"),
+        "#!/bin/sh\necho goodbye world",
+    )?;
+    set_executable(harness.join("bogus/goodbye"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 2 unshimmed commands (2 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello" "goodbye", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 2 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    std::fs::remove_file(harness.join("bogus/hello"))?;
+
+    assert_cmd!(harness, quickenv "unshim" "--prune-broken", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Removed broken shim hello
+    Removed 1 shims from [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv shim <command>' to add them again
+    "###);
+    assert_cmd!(harness, quickenv "list", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    goodbye
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_path_only() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export PATH=bogus:$PATH\nexport MYVALUE=canary\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(
+        harness.join("bogus/hello"),
+        "#!/bin/sh\necho \"hello ${MYVALUE:-nobody}\"",
+    )?;
+    set_executable(harness.join("bogus/hello"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "exec" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello canary
+
+    ----- stderr -----
+    "###);
+    harness.set_var("QUICKENV_PATH_ONLY", "1");
+    assert_cmd!(harness, quickenv "exec" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello nobody
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_exit_codes() -> Result<(), Error> {
+    let mut harness = setup()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 3
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to find .envrc in current or any parent directory
+    "###);
+
+    write(harness.join(".envrc"), "exit 2\n")?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 5
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] .envrc exited with status exit status: 2
+    "###);
+
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 7
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] [scrubbed $HOME]/project/.envrc is not trusted. Review its contents, then run 'quickenv trust' to allow executing it
+    "###);
+
+    harness.trust()?;
+    let quickenv_bin = harness.which("quickenv")?.parent().unwrap().to_owned();
+    harness.set_var("PATH", &quickenv_bin);
+    harness.set_var("QUICKENV_PRELUDE", "");
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 6
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to find bash, which is required to run .envrc
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_vars_sort() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export ZZZ=1\nexport AAA=2\nexport MMM=3\nexport BBB=4\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    AAA=2
+    BBB=4
+    MMM=3
+    ZZZ=1
+
+    ----- stderr -----
+    "###);
+
+    let output = harness.quickenv_output(&["resolve"])?;
+    let stdout = String::from_utf8(output.stdout)?;
+    let cache = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("cache: "))
+        .unwrap();
+    let cache_order: Vec<_> = std::fs::read(cache)?
+        .split(|&c| c == b'\0')
+        // the header recording the .envrc
+        .skip(1)
+        .filter(|record| !record.is_empty())
+        .map(|record| record.to_owned())
+        .collect();
+
+    let output = harness.quickenv_output(&["vars", "--sort", "none", "-0"])?;
+    let vars_order: Vec<_> = output
+        .stdout
+        .split(|&c| c == b'\0')
+        .filter(|record| !record.is_empty())
+        .map(|record| record.to_owned())
+        .collect();
+    assert_eq!(vars_order, cache_order);
+    Ok(())
+}
+
+#[test]
+fn test_cache_dir_removed_during_reload() -> Result<(), Error> {
+    let harness = setup()?;
+    // simulates a concurrent cleanup of the cache directory
+    write(
+        harness.join(".envrc"),
+        "rm -r \"$HOME/.quickenv/envs\"\nexport MYVALUE=canary\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=canary
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_exec_shell() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    harness.trust()?;
+
+    // bash passes exported functions through the environment, so they end up in the cache
+    harness.set_var(
+        "QUICKENV_PRELUDE",
+        "hello() { echo \"hello from a function, $MYVALUE\"; }; export -f hello",
+    );
+    harness.set_var("QUICKENV_SHELL", "bash");
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+
+    assert_cmd!(harness, quickenv "exec" "--shell" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello from a function, canary
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "exec" "--shell" "hello" "|" "tr" "a-z" "A-Z", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    HELLO FROM A FUNCTION, CANARY
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "exec" "hello", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to find actual binary
+
+    Caused by:
+        0: failed to find hello
+        1: cannot find binary path
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_exec_login_shell_functions() -> Result<(), Error> {
+    let harness = setup()?;
+    create_dir_all(harness.join("subdir"))?;
+    write(
+        harness.join(".envrc"),
+        "export MYVALUE=canary\necho output of envrc\ngreet() { echo \"hello $1, $MYVALUE, $(basename \"$PWD\")\"; }\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "exec" "--login-shell-functions" "--cwd" "subdir" "greet" "world", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello world, canary, subdir
+
+    ----- stderr -----
+    output of envrc
+    "###);
+    // the .envrc is evaluated again, so no cache is needed
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: false
+    exit_code: 4
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] Run 'quickenv reload' first to generate envvars
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_unshimmed_commands_priority() -> Result<(), Error> {
+    let mut harness = setup()?;
+    for (dir, name) in [("other", "hello"), ("both", "hello"), ("both", "goodbye")] {
+        create_dir_all(harness.join(dir))?;
+        write(harness.join(dir).join(name), "#!/bin/sh\necho hello world")?;
+        set_executable(harness.join(dir).join(name))?;
+    }
+
+    // 'both' is already on PATH, but the .envrc moves it ahead of 'other'. That changes what
+    // 'hello' runs, but not what 'goodbye' runs.
+    let mut path = harness.var("PATH").unwrap().to_owned();
+    path.push(":");
+    path.push(harness.join("other"));
+    path.push(":");
+    path.push(harness.join("both"));
+    harness.set_var("PATH", path);
+
+    write(harness.join(".envrc"), "export PATH=$PWD/both:$PATH\n")?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "--list-candidates", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_vars_changed_only() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export MYVALUE=canary\nexport OTHERVALUE=unchanged\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    harness.set_var("OTHERVALUE", "unchanged");
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=canary
+    OTHERVALUE=unchanged
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars" "--changed-only", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=canary
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_bash_args() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "false | true\nexport MYVALUE=canary\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    harness.set_var("QUICKENV_BASH_ARGS", "-e -o pipefail");
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: false
+    exit_code: 5
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] .envrc exited with status exit status: 1
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_reload_env_file() -> Result<(), Error> {
+    let harness = setup()?;
+    create_dir_all(harness.join("subdir"))?;
+    write(
+        harness.join(".envrc"),
+        r#"export A='with space $HOME'
+export B="it's \"quoted\" \$HOME"
+export C=$'line1\nline2'
+"#,
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload" "--env-file" "out.env" "subdir", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, cat "out.env", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    A='with space $HOME'
+    B="it's \"quoted\" \$HOME"
+    C="line1\nline2"
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, bash "-c" "set -a; . ./out.env; printf '%s|' \"$A\" \"$B\"", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    with space $HOME|it's "quoted" $HOME|
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_reload_capture_output() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "echo to stdout\necho to stderr >&2\nexport A=1\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload" "--quiet" "--capture-output" "envrc.log", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    to stderr
+    "###);
+    // stdout and stderr are read concurrently, so their relative order is not stable
+    assert_cmd!(harness, sort "envrc.log", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    to stderr
+    to stdout
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_reload_pty() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "echo output of envrc\nif [ -t 1 ]; then export TTY=yes; else export TTY=no; fi\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    output of envrc
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    TTY=no
+
+    ----- stderr -----
+    "###);
+
+    harness.set_var("QUICKENV_PTY", "1");
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    output of envrc
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    TTY=yes
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_reload_no_input() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "if read -r -p 'continue? ' answer; then export ANSWER=\"$answer\"; else export ANSWER=eof; fi\n",
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload" "--no-input", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    ANSWER=eof
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_skip_foreign_shims() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export PATH=$PWD/vendored:$PWD/bogus:$PATH\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+    // a shim that is not in quickenv's bin directory
+    create_dir_all(harness.join("vendored"))?;
+    std::os::unix::fs::symlink(harness.which("quickenv")?, harness.join("vendored/hello"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "exec" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello world
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "which" "--pretend-shimmed" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [scrubbed $HOME]/project/bogus/hello
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_cache_stats() -> Result<(), Error> {
+    let harness = setup()?;
+
+    assert_cmd!(harness, quickenv "cache" "stats", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    dir: [scrubbed $HOME]/.quickenv/envs/
+    count: 0
+    total_size: 0
+
+    ----- stderr -----
+    "###);
+
+    let envs = harness.join("../.quickenv/envs");
+    create_dir_all(&envs)?;
+    write(envs.join("a".repeat(64)), "FOO=bar\0")?;
+    write(envs.join("b".repeat(64)), "HELLO=world\0")?;
+    // not a cache
+    write(envs.join("b".repeat(64) + ".failed"), "oops")?;
+
+    let output = harness.quickenv_output(&["cache", "stats", "--json"])?;
+    assert!(output.status.success(), "{:?}", output);
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(stats["count"], 2);
+    assert_eq!(stats["total_size"], 20);
+    assert!(stats["oldest"]["path"].is_string());
+    assert!(stats["newest"]["path"].is_string());
+    Ok(())
+}
+
+#[test]
+fn test_gc() -> Result<(), Error> {
+    let harness = setup()?;
+    let envs = harness.join("../.quickenv/envs");
+    create_dir_all(&envs)?;
+    let make_old = |path: &Path| {
+        std::fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(40 * 86400))
+    };
+    let old = envs.join("a".repeat(64));
+    write(&old, "FOO=bar\0")?;
+    make_old(&old)?;
+    // deleted with its cache, even though it is newer
+    write(old.with_extension("failed"), "oops")?;
+    let new = envs.join("b".repeat(64));
+    write(&new, "HELLO=world\0")?;
+    // markers without a cache expire on their own
+    let old_marker = envs.join("c".repeat(64) + ".failed");
+    write(&old_marker, "oops")?;
+    make_old(&old_marker)?;
+    let new_marker = envs.join("d".repeat(64) + ".failed");
+    write(&new_marker, "oops")?;
+
+    assert_cmd!(harness, quickenv "gc" "--max-age" "30d" "--dry-run", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [scrubbed $HOME]/.quickenv/envs/[cache key] (40d old)
+    [scrubbed $HOME]/.quickenv/envs/[cache key].failed (40d old)
+
+    ----- stderr -----
+    Would delete 1 caches, freeing 16 bytes.
+    "###);
+    assert!(old.exists());
+
+    assert_cmd!(harness, quickenv "gc" "--max-age" "30d", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [scrubbed $HOME]/.quickenv/envs/[cache key] (40d old)
+    [scrubbed $HOME]/.quickenv/envs/[cache key].failed (40d old)
+
+    ----- stderr -----
+    Deleted 1 caches, freeing 16 bytes.
+    "###);
+    assert!(!old.exists());
+    assert!(!old.with_extension("failed").exists());
+    assert!(new.exists());
+    assert!(!old_marker.exists());
+    assert!(new_marker.exists());
+    Ok(())
+}
+
+#[test]
+fn test_gc_unchanged_reload() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export FOO=bar\n")?;
+    harness.trust()?;
+    let output = harness.quickenv_output(&["reload", "--quiet"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    let envs = harness.join("../.quickenv/envs");
+    let cache = std::fs::read_dir(&envs)?
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_none())
+        .unwrap();
+    let old_mtime = std::time::SystemTime::now() - std::time::Duration::from_secs(40 * 86400);
+    std::fs::File::options()
+        .write(true)
+        .open(&cache)?
+        .set_modified(old_mtime)?;
+    std::fs::File::options()
+        .write(true)
+        .open(cache.with_extension("reloaded"))?
+        .set_modified(old_mtime)?;
+
+    // the cache is not rewritten, but the reload still counts as using it
+    let output = harness.quickenv_output(&["reload", "--quiet"])?;
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(std::fs::metadata(&cache)?.modified()?, old_mtime);
+
+    let missing = harness.join("../.quickenv/missing");
+    create_dir_all(&missing)?;
+    let old_missing = missing.join("a".repeat(64));
+    write(&old_missing, "")?;
+    std::fs::File::options()
+        .write(true)
+        .open(&old_missing)?
+        .set_modified(old_mtime)?;
+    let new_missing = missing.join("b".repeat(64));
+    write(&new_missing, "")?;
+
+    assert_cmd!(harness, quickenv "gc" "--max-age" "30d", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Deleted 0 caches, freeing 0 bytes.
+    "###);
+    assert!(cache.exists());
+    assert!(!old_missing.exists());
+    assert!(new_missing.exists());
+    Ok(())
+}
+
+#[test]
+fn test_shim_replace_atomic() -> Result<(), Error> {
+    let harness = setup()?;
+    let shim_path = harness.join("../.quickenv/bin/hello");
+
+    let output = harness.quickenv_output(&["shim", "--no-verify", "hello"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    // best-effort: the shim must never disappear while it is being re-created
+    let done = std::sync::atomic::AtomicBool::new(false);
+    std::thread::scope(|scope| -> Result<(), Error> {
+        let checker = scope.spawn(|| {
+            while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                assert!(std::fs::symlink_metadata(&shim_path).is_ok());
+            }
+        });
+
+        for _ in 0..20 {
+            let output = harness.quickenv_output(&["shim", "--no-verify", "hello"])?;
+            assert!(output.status.success(), "{:?}", output);
+        }
+
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        checker.join().unwrap();
+        Ok(())
+    })?;
+
+    let leftovers: Vec<_> = std::fs::read_dir(harness.join("../.quickenv/bin"))?
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(leftovers, ["hello"]);
+    Ok(())
+}
+
+#[test]
+fn test_shim_exclude() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=$PWD/bogus:$PATH\n")?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    for name in ["hello", "goodbye", "sudo", "other"] {
+        write(
+            harness.join("bogus").join(name),
+            "#!/bin/sh\necho hello world",
+        )?;
+        set_executable(harness.join("bogus").join(name))?;
+    }
+    write(
+        harness.join(".quickenv.toml"),
+        "shim_exclude = [\"other\"]\n",
+    )?;
+
+    assert_cmd!(harness, quickenv "reload" "--quiet", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 4 unshimmed commands (4 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "--list-candidates" "--exclude" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    goodbye
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "shim" "--yes" "--exclude" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Found these unshimmed commands in your .envrc:
+
+    goodbye
+
+    Quickenv will create this new shim binary in [scrubbed $HOME]/.quickenv/bin/.
+    Inside of [scrubbed $HOME]/project, those commands will run with .envrc enabled.
+    Outside, they will run normally.
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    Use 'quickenv shim <command>' to run additional commands with .envrc enabled.
+    "###);
+    assert!(!harness.join("../.quickenv/bin/hello").exists());
+    assert!(!harness.join("../.quickenv/bin/sudo").exists());
+    assert!(!harness.join("../.quickenv/bin/other").exists());
+
+    // the built-in excludes can be replaced
+    harness.set_var("QUICKENV_SHIM_EXCLUDE", "");
+    assert_cmd!(harness, quickenv "shim" "--list-candidates", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello
+    sudo
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_exec_vars() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export A=1 B=2 C=3\nexport PATH=$PWD/tools:$PATH\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("tools"))?;
+    write(
+        harness.join("tools/show"),
+        "#!/bin/sh\necho A=$A B=$B C=$C\ncase $PATH in *tools*) echo with tools;; esac\n",
+    )?;
+    set_executable(harness.join("tools/show"))?;
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "1");
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    assert_cmd!(harness, quickenv "exec" "--vars" "A,B" "show", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    A=1 B=2 C=
+    with tools
+
+    ----- stderr -----
+    "###);
+    // the program is still found, but does not get the .envrc's PATH
+    assert_cmd!(harness, quickenv "exec" "--vars" "C,-PATH" "show", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    A= B= C=3
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_exec_any() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export PATH=/usr/bin:/bin\nexport MYVALUE=hello\n",
+    )?;
+    harness.trust()?;
+    // only on the PATH quickenv is invoked with
+    create_dir_all(harness.join("tools"))?;
+    write(
+        harness.join("tools/greet"),
+        "#!/bin/sh\necho $MYVALUE world",
+    )?;
+    set_executable(harness.join("tools/greet"))?;
+    harness.prepend_path(harness.join("tools"));
+    // the number of commands in /usr/bin depends on the system
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "1");
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "exec" "greet", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] failed to find actual binary
+
+    Caused by:
+        0: failed to find greet
+        1: cannot find binary path
+    "###);
+    assert_cmd!(harness, quickenv "exec" "--any" "greet", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    hello world
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_exec_measure() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    harness.trust()?;
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    let output = harness.quickenv_output(&["exec", "--measure", "true"])?;
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr)?;
+    let mut phases = Vec::new();
+    for line in stderr.lines() {
+        let (phase, millis) = line
+            .strip_prefix("measure: ")
+            .and_then(|x| x.split_once(' '))
+            .unwrap_or_else(|| panic!("unexpected line {:?}", line));
+        millis.strip_suffix("ms").unwrap().parse::<f64>()?;
+        phases.push(phase);
+    }
+    assert_eq!(phases, ["resolve", "run"]);
+    Ok(())
+}
+
+#[test]
+fn test_exec_from_cache() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "exec" "--from-cache" "sh" "-c" "echo $MYVALUE", @r###"
+    success: false
+    exit_code: 4
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] No cached variables for [scrubbed $HOME]/project/.envrc, run 'quickenv reload' first
+    "###);
+    // the lenient default runs the program without the .envrc
+    assert_cmd!(harness, quickenv "exec" "sh" "-c" "echo $MYVALUE", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+
+    ----- stderr -----
+    "###);
+
+    harness.set_var("QUICKENV_NO_SHIM_WARNINGS", "1");
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
+    assert_cmd!(harness, quickenv "exec" "--from-cache" "sh" "-c" "echo $MYVALUE", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    canary
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_exec_no_path_strip() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=canary\n")?;
+    harness.trust()?;
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    assert_cmd!(harness, quickenv "exec" "sh" "-c" r#"case ":$PATH:" in *":$HOME/.quickenv/bin:"*) echo kept;; *) echo stripped;; esac"#, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    stripped
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "exec" "--no-path-strip" "sh" "-c" r#"case ":$PATH:" in *":$HOME/.quickenv/bin:"*) echo kept;; *) echo stripped;; esac"#, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    kept
+
+    ----- stderr -----
+    [WARN quickenv] --no-path-strip is for debugging only: [scrubbed $HOME]/.quickenv/bin/ stays on PATH, and shims may recurse endlessly
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_marker_in_value() -> Result<(), Error> {
+    let harness = setup()?;
+    // the nonce is not exported, but the .envrc can still find it
+    write(
+        harness.join(".envrc"),
+        r#"
+nonce=$(compgen -v __quickenv_root_)
+export TRICKY=$'before\n// END QUICKENV-AFTER '"${nonce#__quickenv_root_}"$'\nafter'
+export ZZZ=last
+"#,
+    )?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    let output = harness.quickenv_output(&["exec", "printenv", "TRICKY"])?;
+    assert!(output.status.success(), "{:?}", output);
+    let value = String::from_utf8(output.stdout)?;
+    let lines: Vec<_> = value.lines().collect();
+    assert_eq!(lines.len(), 3, "{value:?}");
+    assert_eq!(lines[0], "before");
+    assert!(lines[1].starts_with("// END QUICKENV-AFTER "));
+    assert_eq!(lines[2], "after");
+    assert_cmd!(harness, quickenv "exec" "printenv" "ZZZ", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    last
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_version_verbose() -> Result<(), Error> {
+    let harness = setup()?;
+
+    let output = harness.quickenv_output(&["version", "--verbose"])?;
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout)?;
+    let home = harness.join("../.quickenv/");
+    let home = home.to_str().unwrap().replace("project/../", "");
+    assert!(
+        stdout.contains(&format!("quickenv_home: {home}\n")),
+        "{stdout}"
+    );
+    assert!(stdout.contains("\ntarget: "), "{stdout}");
+
+    let output = harness.quickenv_output(&["version", "--json"])?;
+    assert!(output.status.success(), "{:?}", output);
+    let version: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(version["quickenv_home"], home.as_str());
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    Ok(())
+}
+
+#[test]
+fn test_reload_restricted() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=hello\n")?;
+    harness.trust()?;
+    write(
+        harness.join("../injected.sh"),
+        "echo injected code\nexport INJECTED=1\n",
+    )?;
+    harness.set_var("BASH_ENV", harness.join("../injected.sh"));
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    injected code
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "reload" "--restricted", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=hello
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_reload_json() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "echo loading\nexport PATH=$PWD/bogus:$PATH\nexport MYVALUE=hello\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(harness.join("bogus/hello"), "#!/bin/sh\necho hello world")?;
+    set_executable(harness.join("bogus/hello"))?;
+    write(harness.join("bogus/rm"), "#!/bin/sh\necho not removing")?;
+    set_executable(harness.join("bogus/rm"))?;
+
+    let output = harness.quickenv_output(&["reload", "--json"])?;
+    assert!(output.status.success(), "{:?}", output);
+    // the output of the .envrc must not end up in the JSON
+    assert_eq!(output.stderr, b"loading\n");
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(result["success"], true);
+    assert_eq!(result["changed"], 2);
+    assert_eq!(result["variables"], 2);
+    // like the warning without '--json', this includes commands 'quickenv shim' won't recommend
+    assert_eq!(result["unshimmed"], serde_json::json!(["hello", "rm"]));
+    let cache = result["cache"].as_str().unwrap();
+    assert!(std::fs::read_to_string(cache)?.contains("MYVALUE=hello\0"));
+
+    let output = harness.quickenv_output(&["reload", "--json"])?;
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(result["changed"], 0);
+
+    write(harness.join(".envrc"), "exit 1\n")?;
+    harness.trust()?;
+    let output = harness.quickenv_output(&["reload", "--json"])?;
+    assert!(!output.status.success(), "{:?}", output);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(result["success"], false);
+    assert_eq!(result["error"], ".envrc exited with status exit status: 1");
+    Ok(())
+}
+
+#[test]
+fn test_reload_diff_exit_code() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export MYVALUE=hello\nexport OTHER=1\n",
+    )?;
+    harness.trust()?;
+    let output = harness.quickenv_output(&["reload"])?;
+    assert!(output.status.success(), "{:?}", output);
+
+    assert_cmd!(harness, quickenv "reload" "--diff-exit-code", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+
+    write(
+        harness.join(".envrc"),
+        "export MYVALUE=bye\nexport NEWVALUE=1\n",
+    )?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload" "--diff-exit-code", @r###"
+    success: false
+    exit_code: 1
+    ----- stdout -----
+
+    ----- stderr -----
+    [ERROR quickenv] the cache of [scrubbed $HOME]/project/.envrc was out of date, these variables changed: MYVALUE, NEWVALUE, OTHER
+    "###);
+
+    // the cache was updated anyway
+    assert_cmd!(harness, quickenv "reload" "--diff-exit-code", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_doctor_missing_path_dir() -> Result<(), Error> {
+    let harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export PATH=$PWD/toolchain/bin:$PATH\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("toolchain/bin"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    let output = harness.quickenv_output(&["doctor"])?;
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(
+        stdout.contains("ok: all directories on the cached PATH exist\n"),
+        "{stdout}"
+    );
+
+    // the toolchain was uninstalled
+    std::fs::remove_dir_all(harness.join("toolchain"))?;
+    let output = harness.quickenv_output(&["doctor"])?;
+    assert!(!output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout)?;
+    let expected = format!(
+        "problem: {} is on the cached PATH, but does not exist anymore. Run 'quickenv reload'\n",
+        harness.join("toolchain/bin").display()
+    );
+    assert!(stdout.contains(&expected), "{stdout}");
+    assert_eq!(
+        String::from_utf8(output.stderr)?,
+        "[ERROR quickenv] found 1 problem\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_pinned_locale() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export SORTED=\"$(printf 'b\\nB\\na\\nA\\n' | sort | tr '\\n' ,)\"\nexport SEEN_LOCALE=$LC_ALL\n",
+    )?;
+    harness.trust()?;
+    harness.set_var("LC_ALL", "en_US.UTF-8");
+    harness.set_var("QUICKENV_LOCALE", "C");
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    SEEN_LOCALE=C
+    SORTED=A,B,a,b,
+
+    ----- stderr -----
+    "###);
+
+    harness.set_var("QUICKENV_NATIVE_BEFORE_ENV", "1");
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    SEEN_LOCALE=C
+    SORTED=A,B,a,b,
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_shim_raw_suffix() -> Result<(), Error> {
+    let mut harness = setup()?;
+    create_dir_all(harness.join("../tools"))?;
+    write(harness.join("../tools/tool"), "#!/bin/sh\necho \"A=$A\"")?;
+    set_executable(harness.join("../tools/tool"))?;
+    harness.set_var("PATH", {
+        let mut path = harness.var("PATH").unwrap().to_owned();
+        path.push(":");
+        path.push(harness.join("../tools"));
+        path
+    });
+    write(harness.join(".envrc"), "export A=from-envrc\n")?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "shim" "tool" "tool-raw", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 2 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert_cmd!(harness, tool, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    A=from-envrc
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, sh "-c" "tool-raw", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    A=
+
+    ----- stderr -----
+    "###);
+
+    // an executable that is actually called like that is run instead, with the .envrc
+    write(
+        harness.join("../tools/tool-raw"),
+        "#!/bin/sh\necho \"real tool-raw, A=$A\"",
+    )?;
+    set_executable(harness.join("../tools/tool-raw"))?;
+    assert_cmd!(harness, sh "-c" "tool-raw", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    real tool-raw, A=from-envrc
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_shim_target() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export PATH=$PWD/bogus:$PATH\n")?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(
+        harness.join("bogus/python3"),
+        "#!/bin/sh\necho python3 called with \"$@\"",
+    )?;
+    set_executable(harness.join("bogus/python3"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "--target" "python=python3", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert_cmd!(harness, python "--version", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    python3 called with --version
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "which" "python", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [scrubbed $HOME]/project/bogus/python3
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "shim" "--target" "python", @r###"
+    success: false
+    exit_code: 2
+    ----- stdout -----
+
+    ----- stderr -----
+    error: invalid value for one of the arguments
+    "###);
+
+    assert_cmd!(harness, quickenv "unshim" "python", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Removed 1 shims from [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv shim <command>' to add them again
+    "###);
+    assert!(!harness.join("../.quickenv/aliases/python").exists());
+
+    // shimming again without '--target' drops the alias
+    let output = harness.quickenv_output(&["shim", "--target", "python3=python", "--no-verify"])?;
+    assert!(output.status.success(), "{:?}", output);
+    assert_cmd!(harness, quickenv "shim" "python3", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    python3 no longer runs python.
+    created no new shims.
+    "###);
+    assert!(!harness.join("../.quickenv/aliases/python3").exists());
+    assert_cmd!(harness, python3 "--version", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    python3 called with --version
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_reload_streams_output() -> Result<(), Error> {
+    use std::io::BufRead;
+
+    let harness = setup()?;
+    write(harness.join(".envrc"), "echo first\nsleep 1\necho second\n")?;
+    harness.trust()?;
+
+    let mut child = std::process::Command::new(harness.which("quickenv")?)
+        .current_dir(&harness.cwd)
+        .env_remove("QUICKENV_PRELUDE")
+        .envs(&harness.env)
+        .arg("reload")
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+
+    let mut stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line)?;
+    assert_eq!(line, "first\n");
+    // the first line arrived while the .envrc was still running
+    assert!(child.try_wait()?.is_none());
+
+    line.clear();
+    stdout.read_line(&mut line)?;
+    assert_eq!(line, "second\n");
+    assert!(child.wait()?.success());
+    Ok(())
+}
+
+#[test]
+fn test_cache_header() -> Result<(), Error> {
+    let harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=hello\n")?;
+    harness.trust()?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    let output = harness.quickenv_output(&["resolve", "--json"])?;
+    let resolved: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    let cache = resolved["cache"].as_str().unwrap();
+    let envrc = resolved["envrc"].as_str().unwrap();
+    assert_eq!(
+        std::fs::read(cache)?,
+        format!("#quickenv-cache envrc={envrc}\0MYVALUE=hello\0").as_bytes()
+    );
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=hello
+
+    ----- stderr -----
+    "###);
+    let output = harness.quickenv_output(&["cache", "stats", "--json"])?;
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(stats["projects"], serde_json::json!([envrc]));
+
+    write(
+        harness.join(".envrc"),
+        "export ZZZ=1\nexport MYVALUE=hello\n",
+    )?;
+    harness.trust()?;
+    assert_cmd!(harness, quickenv "reload" "--legacy-format", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+    assert_eq!(std::fs::read(cache)?, b"MYVALUE=hello\nZZZ=1\n");
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=hello
+    ZZZ=1
+
+    ----- stderr -----
+    "###);
+    Ok(())
+}
+
+#[test]
+fn test_symlinked_project() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(harness.join(".envrc"), "export MYVALUE=hello\n")?;
+    harness.trust()?;
+    std::os::unix::fs::symlink(&harness.cwd, harness.join("../link"))?;
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+
+    let link = harness.join("../link");
+    let real_cwd = std::mem::replace(&mut harness.cwd, link);
+    assert_cmd!(harness, quickenv "resolve", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    envrc: [scrubbed $HOME]/project/.envrc
+    root: [scrubbed $HOME]/project
+    cache: [scrubbed $HOME]/.quickenv/envs/[cache key]
+    key: [cache key]
+    cache_exists: true
+    cache_fresh: true
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "vars", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=hello
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "reload" "../link", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    "###);
+
+    let caches = std::fs::read_dir(real_cwd.join("../.quickenv/envs"))?
+        .filter(|entry| entry.as_ref().unwrap().path().extension().is_none())
+        .count();
+    assert_eq!(caches, 1);
+    Ok(())
+}
+
+#[test]
+fn test_deny() -> Result<(), Error> {
+    let mut harness = setup()?;
+    write(
+        harness.join(".envrc"),
+        "export PATH=$PWD/bogus:$PATH\nexport MYVALUE=hello\n",
+    )?;
+    harness.trust()?;
+    create_dir_all(harness.join("bogus"))?;
+    write(
+        harness.join("bogus/hello"),
+        "#!/bin/sh\necho \"MYVALUE=$MYVALUE\"",
+    )?;
+    set_executable(harness.join("bogus/hello"))?;
+    // outside of the .envrc, 'hello' is a different program
+    create_dir_all(harness.join("../system"))?;
+    write(
+        harness.join("../system/hello"),
+        "#!/bin/sh\necho \"system hello, MYVALUE=$MYVALUE\"",
+    )?;
+    set_executable(harness.join("../system/hello"))?;
+    harness.set_var("PATH", {
+        let mut path = harness.var("PATH").unwrap().to_owned();
+        path.push(":");
+        path.push(harness.join("../system"));
+        path
+    });
+
+    assert_cmd!(harness, quickenv "reload", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    [WARN quickenv] 1 unshimmed commands (1 new). Use 'quickenv shim' to make them available.
+    Set QUICKENV_NO_SHIM_WARNINGS=1 to silence this message.
+    "###);
+    assert_cmd!(harness, quickenv "shim" "hello", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Created 1 new shims in [scrubbed $HOME]/.quickenv/bin/.
+    Use 'quickenv unshim <command>' to remove them again.
+    "###);
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=hello
+
+    ----- stderr -----
+    "###);
+
+    assert_cmd!(harness, quickenv "deny", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Denied [scrubbed $HOME]/project. Shims will not load any .envrc there.
+    "###);
+    assert_cmd!(harness, quickenv "deny" "--list", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    [scrubbed $HOME]/project
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    system hello, MYVALUE=
+
+    ----- stderr -----
+    "###);
+
+    assert_cmd!(harness, quickenv "deny" "--remove" ".", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Removed [scrubbed $HOME]/project from the deny list.
+    "###);
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    MYVALUE=hello
+
+    ----- stderr -----
+    "###);
+    assert_cmd!(harness, quickenv "deny" "../*", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Denied [scrubbed $HOME]/*. Shims will not load any .envrc there.
+    "###);
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    system hello, MYVALUE=
+
+    ----- stderr -----
+    "###);
+
+    // the project is found under its canonical path, so a symlink to it is resolved
+    let output = harness.quickenv_output(&["deny", "--remove", "../*"])?;
+    assert!(output.status.success(), "{:?}", output);
+    std::os::unix::fs::symlink(&harness.cwd, harness.join("../link"))?;
+    assert_cmd!(harness, quickenv "deny" "../link", @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+
+    ----- stderr -----
+    Denied [scrubbed $HOME]/project. Shims will not load any .envrc there.
+    "###);
+    assert_cmd!(harness, hello, @r###"
+    success: true
+    exit_code: 0
+    ----- stdout -----
+    system hello, MYVALUE=
 
     ----- stderr -----
     "###);
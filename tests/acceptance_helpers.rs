@@ -5,6 +5,7 @@ use std::fs::{create_dir_all, set_permissions, Permissions};
 use std::os::unix::fs::symlink;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
 
 use anyhow::Error;
 use tempfile::TempDir;
@@ -44,6 +45,17 @@ impl Harness {
             ),
             "[scrubbed usr-bin2]",
         );
+        insta_settings.add_filter(
+            &regex::escape(
+                std::fs::canonicalize("target/debug/quickenv")
+                    .unwrap()
+                    .to_str()
+                    .unwrap(),
+            ),
+            "[quickenv binary]",
+        );
+        // cache filenames are derived from the (random) location of the project
+        insta_settings.add_filter(r"\b[0-9a-f]{64}\b", "[cache key]");
         insta_settings
     }
 
@@ -71,6 +83,26 @@ impl Harness {
     pub fn which(&self, binary_name: impl AsRef<OsStr>) -> which::Result<PathBuf> {
         which::which_in(binary_name, self.var("PATH"), &self.cwd)
     }
+
+    /// Run quickenv with the given arguments and return its output, for assertions that don't fit
+    /// into a snapshot.
+    pub fn quickenv_output(&self, args: &[&str]) -> Result<Output, Error> {
+        Ok(Command::new(self.which("quickenv")?)
+            .current_dir(&self.cwd)
+            .env_remove("QUICKENV_PRELUDE")
+            .env_remove("COLUMNS")
+            .envs(&self.env)
+            .args(args)
+            .output()?)
+    }
+
+    /// Run 'quickenv trust' in the current directory, such that 'quickenv reload' is allowed to
+    /// execute the .envrc.
+    pub fn trust(&self) -> Result<(), Error> {
+        let output = self.quickenv_output(&["trust"])?;
+        assert!(output.status.success(), "{:?}", output);
+        Ok(())
+    }
 }
 
 impl Drop for Harness {
@@ -123,6 +155,7 @@ macro_rules! assert_cmd {
             Command::new($harness.which(stringify!($program_name))?)
             .current_dir(&$harness.cwd)
             .env_remove("QUICKENV_PRELUDE")
+            .env_remove("COLUMNS")
             .envs(&$harness.env)
             $(.arg($arg))*,
             $($insta_args)*